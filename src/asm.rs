@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{Addr, Instruction, VIndex, VValue, PROGRAM_START, GP_REGISTERS_COUNT};
+use crate::error::Chip8Error;
+
+/// Nested includes allowed before assuming a file includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Guards constants defined in terms of each other
+const MAX_SYMBOL_DEPTH: usize = 32;
+
+/// Assembles source using the mnemonics of the disassembler, includes being
+/// resolved from the current directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, Chip8Error> {
+    let mut assembler = Assembler::new();
+    assembler.parse(source, "<source>", Path::new("."), 0)?;
+    assembler.encode()
+}
+
+/// Assembles a file, includes being resolved from its directory
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Vec<u8>, Chip8Error> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let mut assembler = Assembler::new();
+    assembler.parse(&source, &path.display().to_string(), &directory(path), 0)?;
+    assembler.encode()
+}
+
+fn directory(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[derive(Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn error(&self, message: String) -> Chip8Error {
+        Chip8Error::Assembly { file: self.file.clone(), line: self.line, column: self.column, message }
+    }
+}
+
+/// Operand text and where it starts
+#[derive(Clone)]
+struct Operand {
+    text: String,
+    location: Location,
+}
+
+enum Kind {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<Operand>),
+    Words(Vec<Operand>),
+}
+
+struct Statement {
+    location: Location,
+    addr: Addr,
+    kind: Kind,
+}
+
+/// Operand once the registers and keywords are recognised
+enum Arg {
+    V(u8),
+    Range(u8, u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Long(Operand),
+    Value(Operand),
+}
+
+enum Symbol {
+    Label(Addr),
+    Constant(Operand),
+}
+
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    addr: Addr,
+}
+
+impl Assembler {
+
+    fn new() -> Assembler {
+        Assembler {
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            addr: PROGRAM_START,
+        }
+    }
+
+    /// First pass: splits statements, gives labels their address and records constants
+    fn parse(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<(), Chip8Error> {
+        for (number, line) in source.lines().enumerate() {
+            let code = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let mut rest = code;
+            let mut column = 1;
+            let location = |column: usize| Location { file: file.to_string(), line: number + 1, column };
+
+            // Leading label
+            let (skipped, word) = next_word(rest);
+            if let Some(name) = word.strip_suffix(':') {
+                let start = column + skipped;
+                check_name(name, &location(start))?;
+                self.define(name, Symbol::Label(self.addr), &location(start))?;
+                column = start + word.len();
+                rest = &rest[skipped + word.len()..];
+            }
+
+            // NAME = value
+            if let Some(position) = rest.find('=') {
+                let start = column + rest.len() - rest.trim_start().len();
+                let name = rest[..position].trim();
+                check_name(name, &location(start))?;
+                let operands = split_operands(&rest[position + 1..], column + position + 1, &location(start));
+                match operands.as_slice() {
+                    [value] => self.define(name, Symbol::Constant(value.clone()), &location(start))?,
+                    _ => return Err(location(start).error(format!("constant {} needs exactly one value", name))),
+                }
+                continue;
+            }
+
+            let (skipped, word) = next_word(rest);
+            if word.is_empty() {
+                continue;
+            }
+            let start = column + skipped;
+            let after = &rest[skipped + word.len()..];
+            let after_column = start + word.len();
+
+            let operands = split_operands(after, after_column, &location(start));
+            let upper = word.to_uppercase();
+            match upper.as_str() {
+                "INCLUDE" => {
+                    let name = match operands.as_slice() {
+                        [name] if name.text.len() >= 2 && name.text.starts_with('"') && name.text.ends_with('"') => {
+                            &name.text[1..name.text.len() - 1]
+                        }
+                        _ => return Err(location(start).error("include needs a quoted file name".to_string())),
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location(start).error(format!("includes nested deeper than {}", MAX_INCLUDE_DEPTH)))
+                    }
+                    let path = dir.join(name);
+                    let source = fs::read_to_string(&path).map_err(|e| {
+                        location(start).error(format!("can't include {}: {}", path.display(), e))
+                    })?;
+                    self.parse(&source, &path.display().to_string(), &directory(&path), depth + 1)?;
+                }
+                "DB" | "DW" => {
+                    if operands.is_empty() {
+                        return Err(location(start).error(format!("{} needs at least one value", word)))
+                    }
+                    let size = if upper == "DB" { 1 } else { 2 };
+                    let addr = self.addr;
+                    self.addr += size * operands.len();
+                    let kind = if upper == "DB" { Kind::Bytes(operands) } else { Kind::Words(operands) };
+                    self.statements.push(Statement { location: location(start), addr, kind });
+                }
+                _ => {
+                    // LD I, LONG NNNN is the only 4 bytes instruction
+                    let long = upper == "LD" && operands.len() == 2
+                        && operands[0].text.eq_ignore_ascii_case("I")
+                        && operands[1].text.to_uppercase().starts_with("LONG ");
+                    let addr = self.addr;
+                    self.addr += if long { 4 } else { 2 };
+                    let kind = Kind::Instruction { mnemonic: upper, operands };
+                    self.statements.push(Statement { location: location(start), addr, kind });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<(), Chip8Error> {
+        if self.symbols.contains_key(name) {
+            return Err(location.error(format!("{} is already defined", name)))
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Second pass: every symbol is known, statements become bytes
+    fn encode(&self) -> Result<Vec<u8>, Chip8Error> {
+        let mut rom = Vec::new();
+        for statement in self.statements.iter() {
+            debug_assert_eq!(statement.addr, PROGRAM_START + rom.len());
+            match &statement.kind {
+                Kind::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.value(value, -0x80, 0xFF)? as u8);
+                    }
+                }
+                Kind::Words(values) => {
+                    for value in values {
+                        rom.extend_from_slice(&(self.value(value, -0x8000, 0xFFFF)? as u16).to_be_bytes());
+                    }
+                }
+                Kind::Instruction { mnemonic, operands } => {
+                    let args = operands.iter().map(classify).collect::<Result<Vec<Arg>, Chip8Error>>()?;
+                    rom.extend(self.instruction(mnemonic, &args, &statement.location)?.to_bytes());
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn instruction(&self, mnemonic: &str, args: &[Arg], location: &Location) -> Result<Instruction, Chip8Error> {
+        let v = |x: &u8| *x as VIndex;
+        let kk = |op| self.byte(op);
+        let nnn = |op| self.addr(op);
+        let n = |op| self.nibble(op);
+        let instr = match (mnemonic, args) {
+            ("CLS", []) => Instruction::CLS,
+            ("RET", []) => Instruction::RET,
+            ("SCR", []) => Instruction::SCR,
+            ("SCL", []) => Instruction::SCL,
+            ("EXIT", []) => Instruction::EXIT,
+            ("LOW", []) => Instruction::LOW,
+            ("HIGH", []) => Instruction::HIGH,
+            ("AUDIO", []) => Instruction::AUDIO,
+            ("SCD", [Arg::Value(op)]) => Instruction::SCD(n(op)?),
+            ("SYS", [Arg::Value(op)]) => Instruction::SYS(nnn(op)?),
+            ("JP", [Arg::Value(op)]) => Instruction::JP(nnn(op)?),
+            ("JP", [Arg::V(0), Arg::Value(op)]) => Instruction::JP_V0(nnn(op)?),
+            ("CALL", [Arg::Value(op)]) => Instruction::CALL(nnn(op)?),
+            ("SE", [Arg::V(x), Arg::Value(op)]) => Instruction::SEi(v(x), kk(op)?),
+            ("SNE", [Arg::V(x), Arg::Value(op)]) => Instruction::SNEi(v(x), kk(op)?),
+            ("SE", [Arg::V(x), Arg::V(y)]) => Instruction::SE(v(x), v(y)),
+            ("LD", [Arg::IndirectI, Arg::Range(x, y)]) => Instruction::ST_RANGE(v(x), v(y)),
+            ("LD", [Arg::Range(x, y), Arg::IndirectI]) => Instruction::LD_RANGE(v(x), v(y)),
+            ("LD", [Arg::V(x), Arg::Value(op)]) => Instruction::LDi(v(x), kk(op)?),
+            ("ADD", [Arg::V(x), Arg::Value(op)]) => Instruction::ADDi(v(x), kk(op)?),
+            ("LD", [Arg::V(x), Arg::V(y)]) => Instruction::LD(v(x), v(y)),
+            ("OR", [Arg::V(x), Arg::V(y)]) => Instruction::OR(v(x), v(y)),
+            ("AND", [Arg::V(x), Arg::V(y)]) => Instruction::AND(v(x), v(y)),
+            ("XOR", [Arg::V(x), Arg::V(y)]) => Instruction::XOR(v(x), v(y)),
+            ("ADD", [Arg::V(x), Arg::V(y)]) => Instruction::ADD(v(x), v(y)),
+            ("SUB", [Arg::V(x), Arg::V(y)]) => Instruction::SUB(v(x), v(y)),
+            ("SHR", [Arg::V(x)]) => Instruction::SHR(v(x), v(x)),
+            ("SHR", [Arg::V(x), Arg::V(y)]) => Instruction::SHR(v(x), v(y)),
+            ("SUBN", [Arg::V(x), Arg::V(y)]) => Instruction::SUBN(v(x), v(y)),
+            ("SHL", [Arg::V(x)]) => Instruction::SHL(v(x), v(x)),
+            ("SHL", [Arg::V(x), Arg::V(y)]) => Instruction::SHL(v(x), v(y)),
+            ("SNE", [Arg::V(x), Arg::V(y)]) => Instruction::SNE(v(x), v(y)),
+            ("LD", [Arg::I, Arg::Value(op)]) => Instruction::LD_I(nnn(op)?),
+            ("LD", [Arg::I, Arg::Long(op)]) => Instruction::LD_I_LONG(self.value(op, 0, 0xFFFF)? as Addr),
+            ("RND", [Arg::V(x), Arg::Value(op)]) => Instruction::RNDi(v(x), kk(op)?),
+            ("DRW", [Arg::V(x), Arg::V(y), Arg::Value(op)]) => Instruction::DRW(v(x), v(y), n(op)?),
+            ("SKP", [Arg::V(x)]) => Instruction::SKP(v(x)),
+            ("SKNP", [Arg::V(x)]) => Instruction::SKNP(v(x)),
+            ("PLANE", [Arg::Value(op)]) => Instruction::PLANE(n(op)?),
+            ("LD", [Arg::V(x), Arg::DT]) => Instruction::LD_DT(v(x)),
+            ("LD", [Arg::V(x), Arg::K]) => Instruction::LD_K(v(x)),
+            ("LD", [Arg::DT, Arg::V(x)]) => Instruction::SET_DT(v(x)),
+            ("LD", [Arg::ST, Arg::V(x)]) => Instruction::SET_ST(v(x)),
+            ("ADD", [Arg::I, Arg::V(x)]) => Instruction::ADD_I(v(x)),
+            ("LD", [Arg::F, Arg::V(x)]) => Instruction::LD_F(v(x)),
+            ("LD", [Arg::HF, Arg::V(x)]) => Instruction::LD_HF(v(x)),
+            ("LD", [Arg::B, Arg::V(x)]) => Instruction::LD_B(v(x)),
+            ("PITCH", [Arg::V(x)]) => Instruction::PITCH(v(x)),
+            ("LD", [Arg::IndirectI, Arg::V(x)]) => Instruction::ST_UNTIL(v(x)),
+            ("LD", [Arg::V(x), Arg::IndirectI]) => Instruction::LD_UNTIL(v(x)),
+            ("LD", [Arg::R, Arg::V(x)]) => Instruction::ST_R(v(x)),
+            ("LD", [Arg::V(x), Arg::R]) => Instruction::LD_R(v(x)),
+            _ if is_mnemonic(mnemonic) => {
+                return Err(location.error(format!("invalid operands for {}", mnemonic)))
+            }
+            _ => return Err(location.error(format!("unknown mnemonic {}", mnemonic))),
+        };
+        Ok(instr)
+    }
+
+    fn nibble(&self, operand: &Operand) -> Result<u8, Chip8Error> {
+        Ok(self.value(operand, 0, 0xF)? as u8)
+    }
+
+    /// Negative bytes are stored in two's complement, `ADD V0, -1`
+    fn byte(&self, operand: &Operand) -> Result<VValue, Chip8Error> {
+        Ok(self.value(operand, -0x80, 0xFF)? as VValue)
+    }
+
+    fn addr(&self, operand: &Operand) -> Result<Addr, Chip8Error> {
+        Ok(self.value(operand, 0, 0xFFF)? as Addr)
+    }
+
+    /// Evaluates an operand and checks it fits in [min, max]
+    fn value(&self, operand: &Operand, min: i64, max: i64) -> Result<i64, Chip8Error> {
+        let value = self.evaluate(operand, 0)?;
+        if value < min || value > max {
+            return Err(operand.location.error(format!("{} does not fit in {:#X}", value, max)))
+        }
+        Ok(value)
+    }
+
+    /// Sum and differences of numbers, labels and constants
+    fn evaluate(&self, operand: &Operand, depth: usize) -> Result<i64, Chip8Error> {
+        let text = operand.text.as_str();
+        let mut total = 0;
+        let mut sign = 1;
+        let mut start = None;
+        let mut expect_term = true;
+        for (position, c) in text.char_indices().chain(std::iter::once((text.len(), '+'))) {
+            if c == '+' || c == '-' || c.is_whitespace() {
+                if let Some(begin) = start.take() {
+                    let term = Operand {
+                        text: text[begin..position].to_string(),
+                        location: Location { column: operand.location.column + begin, ..operand.location.clone() },
+                    };
+                    total += sign * self.term(&term, depth)?;
+                    sign = 1;
+                    expect_term = false;
+                }
+                if c == '-' {
+                    sign = -sign;
+                    expect_term = true;
+                }
+                else if c == '+' && position < text.len() {
+                    expect_term = true;
+                }
+            }
+            else if start.is_none() {
+                if !expect_term {
+                    let location = Location { column: operand.location.column + position, ..operand.location.clone() };
+                    return Err(location.error(format!("expected + or - in {:?}", text)))
+                }
+                start = Some(position);
+            }
+        }
+        if expect_term {
+            return Err(operand.location.error(format!("incomplete expression {:?}", text)))
+        }
+        Ok(total)
+    }
+
+    fn term(&self, operand: &Operand, depth: usize) -> Result<i64, Chip8Error> {
+        let text = operand.text.as_str();
+        let lower = text.to_lowercase();
+        let number = if let Some(hex) = lower.strip_prefix("0x") {
+            Some(i64::from_str_radix(hex, 16))
+        }
+        else if let Some(binary) = lower.strip_prefix("0b") {
+            Some(i64::from_str_radix(binary, 2))
+        }
+        else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            Some(text.parse())
+        }
+        else {
+            None
+        };
+        match number {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(_)) => Err(operand.location.error(format!("invalid number {}", text))),
+            None => match self.symbols.get(text) {
+                Some(Symbol::Label(addr)) => Ok(*addr as i64),
+                Some(Symbol::Constant(value)) => {
+                    if depth >= MAX_SYMBOL_DEPTH {
+                        return Err(operand.location.error(format!("{} is defined in terms of itself", text)))
+                    }
+                    self.evaluate(value, depth + 1)
+                }
+                None => Err(operand.location.error(format!("undefined symbol {}", text))),
+            }
+        }
+    }
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    [
+        "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SYS", "JP", "CALL",
+        "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+        "DRW", "SKP", "SKNP", "PLANE", "PITCH",
+    ].contains(&mnemonic)
+}
+
+/// Registers and keywords, anything else being an expression
+fn classify(operand: &Operand) -> Result<Arg, Chip8Error> {
+    let upper = operand.text.to_uppercase();
+    let arg = match upper.as_str() {
+        "I" => Arg::I,
+        "[I]" => Arg::IndirectI,
+        "DT" => Arg::DT,
+        "ST" => Arg::ST,
+        "K" => Arg::K,
+        "F" => Arg::F,
+        "HF" => Arg::HF,
+        "B" => Arg::B,
+        "R" => Arg::R,
+        _ => {
+            if let Some(addr) = upper.strip_prefix("LONG ") {
+                let skipped = operand.text.len() - addr.len();
+                return Ok(Arg::Long(Operand {
+                    text: operand.text[skipped..].trim_start().to_string(),
+                    location: Location {
+                        column: operand.location.column + skipped + (addr.len() - addr.trim_start().len()),
+                        ..operand.location.clone()
+                    },
+                }))
+            }
+            if let Some((x, y)) = upper.split_once('-') {
+                if let (Some(x), Some(y)) = (register(x.trim()), register(y.trim())) {
+                    return Ok(Arg::Range(x, y))
+                }
+            }
+            match register(&upper) {
+                Some(x) => Arg::V(x),
+                None => Arg::Value(operand.clone()),
+            }
+        }
+    };
+    Ok(arg)
+}
+
+/// V0 to VF
+fn register(text: &str) -> Option<u8> {
+    let index = text.strip_prefix('V')?;
+    match u8::from_str_radix(index, 16) {
+        Ok(x) if index.len() == 1 && (x as usize) < GP_REGISTERS_COUNT => Some(x),
+        _ => None,
+    }
+}
+
+/// Operand keywords, which classify reads before names
+const KEYWORDS: [&str; 8] = ["I", "DT", "ST", "K", "F", "HF", "B", "R"];
+
+/// Labels and constants: a letter or _ followed by letters, digits or _, other
+/// than registers and keywords
+fn check_name(name: &str, location: &Location) -> Result<(), Chip8Error> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let upper = name.to_uppercase();
+    if !valid || register(&upper).is_some() || KEYWORDS.contains(&upper.as_str()) {
+        return Err(location.error(format!("{:?} can't be used as a name", name)))
+    }
+    Ok(())
+}
+
+/// Leading whitespace length and the first word
+fn next_word(text: &str) -> (usize, &str) {
+    let trimmed = text.trim_start();
+    let skipped = text.len() - trimmed.len();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    (skipped, &trimmed[..end])
+}
+
+/// Comma separated operands, each with the column it starts at
+fn split_operands(text: &str, column: usize, location: &Location) -> Vec<Operand> {
+    if text.trim().is_empty() {
+        return Vec::new()
+    }
+    let mut operands = Vec::new();
+    let mut offset = 0;
+    for part in text.split(',') {
+        let skipped = part.len() - part.trim_start().len();
+        operands.push(Operand {
+            text: part.trim().to_string(),
+            location: Location { column: column + offset + skipped, ..location.clone() },
+        });
+        offset += part.len() + 1;
+    }
+    operands
+}
@@ -0,0 +1,46 @@
+use clap::{Arg, App};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use chip8::asm;
+
+fn main() {
+
+    let matches = App::new("chip-8 assembler")
+        .version("0.0.0")
+        .author("Jules Vranckx")
+        .about("Assembles chip-8 mnemonics, as printed by chip8-disasm, into a binary ROM")
+        .arg(Arg::new("file")
+                 .value_name("SOURCE")
+                 .required(true)
+                 .help("assembly source"))
+        .arg(Arg::new("output")
+                  .short('o')
+                  .long("output")
+                  .takes_value(true)
+                  .value_name("FILE")
+                  .help("ROM to write, - for the standard output, SOURCE with a .ch8 extension by default")
+        )
+        .get_matches();
+
+    let filename = matches.value_of("file").unwrap();
+    let output = match matches.value_of("output") {
+        Some(output) => output.to_string(),
+        None => Path::new(filename).with_extension("ch8").display().to_string(),
+    };
+
+    let rom = match asm::assemble_file(filename) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(-1);
+        }
+    };
+    let written = if output == "-" { io::stdout().lock().write_all(&rom) } else { fs::write(&output, rom) };
+    if let Err(e) = written {
+        eprintln!("Can't write {}: {}", output, e);
+        process::exit(-1);
+    }
+}
@@ -0,0 +1,45 @@
+use clap::{Arg, App};
+use std::fs;
+use std::process;
+
+use chip8::Disassembly;
+
+fn main() {
+
+    let matches = App::new("chip-8 disassembler")
+        .version("0.0.0")
+        .author("Jules Vranckx")
+        .about("Disassembles a binary chip-8 ROM, separating code reachable from 0x200 and data")
+        .arg(Arg::new("file")
+                 .value_name("ROM")
+                 .required(true)
+                 .help("binary program to disassemble"))
+        .arg(Arg::new("output")
+                  .short('o')
+                  .long("output")
+                  .takes_value(true)
+                  .value_name("FILE")
+                  .help("write the listing to FILE instead of the standard output")
+        )
+        .get_matches();
+
+    let filename = matches.value_of("file").unwrap();
+    let rom = match fs::read(filename) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Can't read {}: {}", filename, e);
+            process::exit(-1);
+        }
+    };
+
+    let listing = Disassembly::new(&rom).listing();
+    match matches.value_of("output") {
+        Some(output) => {
+            if let Err(e) = fs::write(output, listing) {
+                eprintln!("Can't write {}: {}", output, e);
+                process::exit(-1);
+            }
+        }
+        None => print!("{}", listing),
+    }
+}
@@ -0,0 +1,216 @@
+use clap::{Arg, App};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::process;
+
+use chip8::{CPU, Addr, Headless, Movie, Quirks};
+use chip8::headless::{KeyPress, Outcome, Until};
+use chip8::screenshot::{self, Format};
+
+/// Exit status when the stop condition was not met before the last frame
+const EXIT_NOT_REACHED: i32 = 2;
+
+fn main() {
+
+    let matches = App::new("chip-8 headless runner")
+        .version("0.0.0")
+        .author("Jules Vranckx")
+        .about("Runs a chip-8 program without a window, then prints or saves the screen")
+        .arg(Arg::new("file")
+                 .value_name("ROM")
+                 .required(true)
+                 .help("program to be executed"))
+        .arg(Arg::new("text input")
+                  .short('t')
+                  .long("text")
+                  .takes_value(false)
+                  .conflicts_with("asm input")
+                  .help("input file as text, raw by default")
+        )
+        .arg(Arg::new("asm input")
+                  .short('a')
+                  .long("asm")
+                  .takes_value(false)
+                  .help("input file as assembly source, raw by default")
+        )
+        .arg(Arg::new("quirks")
+                  .short('q')
+                  .long("quirks")
+                  .takes_value(true)
+                  .value_name("PRESET")
+                  .possible_values(Quirks::preset_names())
+                  .default_value("chip8")
+                  .help("interpreter behaviour to emulate")
+        )
+        .arg(Arg::new("frequency")
+                  .long("frequency")
+                  .takes_value(true)
+                  .value_name("HZ")
+                  .help("instructions executed per second, the emulator default otherwise")
+        )
+        .arg(Arg::new("seed")
+                  .long("seed")
+                  .takes_value(true)
+                  .value_name("SEED")
+                  .help("seed of the random numbers, for reproducible runs")
+        )
+        .arg(Arg::new("frames")
+                  .long("frames")
+                  .takes_value(true)
+                  .value_name("N")
+                  .default_value("600")
+                  .help("60th of a second to run at most, the length of the movie when replaying")
+        )
+        .arg(Arg::new("until pc")
+                  .long("until-pc")
+                  .takes_value(true)
+                  .value_name("ADDR")
+                  .conflicts_with("until loop")
+                  .help("stop when PC reaches the hexadecimal address ADDR")
+        )
+        .arg(Arg::new("until loop")
+                  .long("until-loop")
+                  .takes_value(false)
+                  .help("stop when the program jumps to itself or exits")
+        )
+        .arg(Arg::new("key")
+                  .long("key")
+                  .takes_value(true)
+                  .multiple_occurrences(true)
+                  .value_name("FRAME:KEY[:FRAMES]")
+                  .help("hold hexadecimal KEY down from FRAME on, for FRAMES frames (6 by default)")
+        )
+        .arg(Arg::new("replay")
+                  .long("replay")
+                  .takes_value(true)
+                  .value_name("MOVIE")
+                  .conflicts_with_all(&["key", "seed"])
+                  .help("play the keypad recorded in MOVIE, with its quirks, frequency and seed, checking the screen")
+        )
+        .arg(Arg::new("output")
+                  .short('o')
+                  .long("output")
+                  .takes_value(true)
+                  .value_name("FILE")
+                  .help("write the screen to FILE instead of the standard output")
+        )
+        .arg(Arg::new("format")
+                  .long("format")
+                  .takes_value(true)
+                  .value_name("FORMAT")
+                  .possible_values(["ascii", "pbm", "png"])
+                  .help("image format, guessed from the output extension, ascii by default")
+        )
+        .get_matches();
+
+    let filename = matches.value_of("file").unwrap();
+    let quirks: Quirks = matches.value_of_t("quirks").unwrap_or_else(|e| e.exit());
+    let frequency: Option<u32> = matches.is_present("frequency").then(|| matches.value_of_t("frequency").unwrap_or_else(|e| e.exit()));
+    let frames: u64 = matches.value_of_t("frames").unwrap_or_else(|e| e.exit());
+    let seed: Option<u64> = matches.is_present("seed").then(|| matches.value_of_t("seed").unwrap_or_else(|e| e.exit()));
+    let output = matches.value_of("output");
+    let format = match (matches.value_of("format"), output) {
+        (Some(format), _) => format.parse().unwrap(),
+        (None, Some(output)) => Format::from_extension(output).unwrap_or(Format::Ascii),
+        (None, None) => Format::Ascii,
+    };
+
+    let movie = matches.value_of("replay").map(|path| {
+        match File::open(path).map_err(|e| e.into()).and_then(Movie::read) {
+            Ok(movie) => movie,
+            Err(e) => {
+                eprintln!("Can't read {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    });
+    let frames = match movie.as_ref() {
+        Some(movie) if matches.occurrences_of("frames") == 0 => movie.len() as u64,
+        _ => frames,
+    };
+
+    let mut headless = Headless::new(frames);
+    if let Some(addr) = matches.value_of("until pc") {
+        let digits = addr.trim_start_matches("0x");
+        match Addr::from_str_radix(digits, 16) {
+            Ok(addr) => headless.set_until(Until::Pc(addr)),
+            Err(_) => {
+                eprintln!("{} is not a hexadecimal address", addr);
+                process::exit(1);
+            }
+        }
+    }
+    if matches.is_present("until loop") {
+        headless.set_until(Until::Loop);
+    }
+    for press in matches.values_of("key").into_iter().flatten() {
+        match press.parse::<KeyPress>() {
+            Ok(press) => headless.add_key_press(press),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut cpu = match movie.as_ref() {
+        Some(movie) => CPU::new(Some(movie.frequency()), movie.quirks()),
+        None => CPU::new(frequency, quirks),
+    };
+    let loaded = if matches.is_present("asm input") {
+        cpu.loada(filename)
+    }
+    else if matches.is_present("text input") {
+        cpu.loadt(filename)
+    }
+    else {
+        cpu.loadb(filename)
+    };
+    if let Err(e) = loaded {
+        eprintln!("Can't load {}: {}", filename, e);
+        process::exit(1);
+    }
+    cpu.power_on();
+    if let Some(seed) = seed {
+        cpu.set_seed(seed);
+    }
+    if let Some(movie) = movie {
+        if let Err(e) = movie.start_replay(&mut cpu) {
+            eprintln!("Can't replay: {}", e);
+            process::exit(1);
+        }
+        headless.set_movie(movie);
+    }
+
+    let outcome = match headless.run(&mut cpu) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("PC: {:#05X}, opcode: {:#06X}", cpu.program_counter(), cpu.current_opcode());
+            process::exit(1);
+        }
+    };
+
+    // The screen is written whatever the outcome, it helps understanding why a condition was missed
+    let image = screenshot::encode(cpu.frame_buffer(), format);
+    let written = match output {
+        Some(output) => fs::write(output, image),
+        None => io::stdout().write_all(&image),
+    };
+    if let Err(e) = written {
+        eprintln!("Can't write the screen: {}", e);
+        process::exit(1);
+    }
+
+    match outcome {
+        Outcome::Timeout if matches.is_present("until pc") || matches.is_present("until loop") => {
+            eprintln!("Stop condition not met after {} frames", frames);
+            process::exit(EXIT_NOT_REACHED);
+        }
+        Outcome::Exited { frame } if matches.is_present("until pc") => {
+            eprintln!("Program exited at frame {} before reaching the address", frame);
+            process::exit(EXIT_NOT_REACHED);
+        }
+        _ => {}
+    }
+}
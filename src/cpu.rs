@@ -1,19 +1,52 @@
 #![allow(non_camel_case_types)]
-use std::time::Duration;
-use std::thread::sleep;
-use rand::Rng;
+#![allow(clippy::upper_case_acronyms)]
 use std::fs::File;
+use std::fmt;
 use std::fs;
-use std::io::Read;
-use std::i64;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::asm;
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+use crate::random::{Random, RandomSource};
+use crate::trace::{TraceEntry, TraceHook};
 
 pub const MEMORY_SIZE: usize = 0x1000 ;
+/// XO-CHIP addressable memory
+pub const EXTENDED_MEMORY_SIZE: usize = 0x10000 ;
+/// Memory is copied on write by pages of this size, shared between snapshots otherwise
+pub const MEMORY_PAGE_SIZE: usize = 0x100;
 pub const GP_REGISTERS_COUNT: usize = 16 ;
 pub const STACK_SIZE: usize = 16 ;
 pub const PROGRAM_START: usize = 0x200 ;
 pub const FRAME_BUFFER_LENGTH: usize = 64;
 pub const FRAME_BUFFER_HEIGHT: usize = 32;
+pub const HIRES_FRAME_BUFFER_LENGTH: usize = 128;
+pub const HIRES_FRAME_BUFFER_HEIGHT: usize = 64;
 pub const DEFAULT_FREQUENCY: u32 = 600;
+pub const KEY_COUNT: usize = 16;
+pub const VF: VIndex = 0xF;
+pub const DEFAULT_FONT_ADDRESS: Addr = 0x050;
+pub const FONT_GLYPH_SIZE: usize = 5;
+pub const BIG_FONT_GLYPH_SIZE: usize = 10;
+pub const RPL_FLAGS_COUNT: usize = 16;
+/// Scroll distance of 00FB and 00FC, in pixels of the current resolution
+pub const HORIZONTAL_SCROLL: usize = 4;
+/// XO-CHIP draws on two bit planes, a pixel value is the set of planes it is lit on
+pub const PLANES_MASK: u8 = 0b11;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+/// Square wave close to the usual CHIP-8 beep once played at the default pitch
+pub const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_SIZE] = [
+    0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+    0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+];
+/// First bytes of every save state
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"C8ST";
+/// Bumped each time the save state layout changes
+pub const SAVE_STATE_VERSION: u8 = 4;
 pub const FONT_SET: [u8; 80] = [
     0xF0,
     0x90,
@@ -97,6 +130,26 @@ pub const FONT_SET: [u8; 80] = [
     0x80,
 ];
 
+/// SUPER-CHIP 8x10 digits, extended to A-F as XO-CHIP does
+pub const BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
+
 pub type Addr = usize ;
 pub type StackAdress = usize ;
 pub type VIndex = usize ;
@@ -110,6 +163,7 @@ pub type VValue = u8 ;
 /// Registers strucure
 /// Holds a 16 bytes long array
 /// Defines methods to create a new instance, read from registers and write in it
+#[derive(Clone)]
 struct Registers{
     regs: [VValue; GP_REGISTERS_COUNT]
 }
@@ -121,19 +175,19 @@ impl Registers {
         }
     }
 
-    fn read(&self, index: VIndex) -> Result<VValue, &'static str>{
+    fn read(&self, index: VIndex) -> Result<VValue, Chip8Error>{
         
-        if index >= GP_REGISTERS_COUNT - 1 {
-            return Err("Register index too high. Please use -c to see CHIP-8 caracteristics")
+        if index >= GP_REGISTERS_COUNT {
+            return Err(Chip8Error::RegisterOutOfRange { index })
         }
         
         Ok(self.regs[index])
     }
 
-    fn write(&mut self, index: VIndex, value: VValue) -> Result<(), &'static str> {
+    fn write(&mut self, index: VIndex, value: VValue) -> Result<(), Chip8Error> {
         
-        if index >= GP_REGISTERS_COUNT - 1 {
-            return Err("Register index too high. Please use -c to see CHIP-8 caracteristics")
+        if index >= GP_REGISTERS_COUNT {
+            return Err(Chip8Error::RegisterOutOfRange { index })
         }
 
         self.regs[index] = value ;
@@ -141,55 +195,71 @@ impl Registers {
         Ok(())
     }
 
-    fn set_f(&mut self) -> Result<(), &'static str> {
-        self.regs[15] = 1 ;
+    /// VF is written last by flag producing instructions, so the flag
+    /// wins when VF is also the destination register
+    fn set_f(&mut self, flag: bool) -> Result<(), Chip8Error> {
+        self.regs[VF] = flag as VValue ;
         Ok(())
     }
-
-    fn clr_f(&mut self) -> Result<(), &'static str> {
-        self.regs[15] &= 0x0 ;
-        Ok(())
-    }
-
-    // fn get_f(&self) -> Result<&VValue, &'static str> {
-    //     Ok(&self.regs[15])
-    // }
 }
 
 /// Memory structure
+/// MEMORY_SIZE bytes, or EXTENDED_MEMORY_SIZE for XO-CHIP programs, in pages of
+/// MEMORY_PAGE_SIZE bytes. Clones share the pages neither of them wrote to since,
+/// so snapshots only cost the pages a frame changed
+#[derive(Clone)]
 struct Memory{
-    cells: [CellValue; MEMORY_SIZE]
+    pages: Vec<Arc<[CellValue; MEMORY_PAGE_SIZE]>>
 }
 
 impl Memory {
-    fn new() -> Memory {
+    fn new(size: usize) -> Memory {
         Memory{
-            cells: [0; MEMORY_SIZE]
+            pages: (0..size / MEMORY_PAGE_SIZE).map(|_| Arc::new([0; MEMORY_PAGE_SIZE])).collect()
         }
     }
 
-    fn read(&self, index: Addr) -> Result<CellValue, &'static str>{
+    fn size(&self) -> usize {
+        self.pages.len() * MEMORY_PAGE_SIZE
+    }
+
+    fn read(&self, index: Addr) -> Result<CellValue, Chip8Error>{
         
-        if index >= MEMORY_SIZE {
-            return Err("Memory index too high. Please use -c to see CHIP-8 caracteristics")
+        if index >= self.size() {
+            return Err(Chip8Error::MemoryOutOfRange { addr: index })
         }
         
-        Ok(self.cells[index])
+        Ok(self.pages[index / MEMORY_PAGE_SIZE][index % MEMORY_PAGE_SIZE])
     }
 
-    fn write(&mut self, index: Addr, value: CellValue) -> Result<(), &'static str> {
+    fn write(&mut self, index: Addr, value: CellValue) -> Result<(), Chip8Error> {
         
-        if index >= MEMORY_SIZE {
-            return Err("Memory index too high. Please use -c to see CHIP-8 caracteristics")
+        if index >= self.size() {
+            return Err(Chip8Error::MemoryOutOfRange { addr: index })
         }
 
-        self.cells[index] = value ;
+        Arc::make_mut(&mut self.pages[index / MEMORY_PAGE_SIZE])[index % MEMORY_PAGE_SIZE] = value ;
+
+        Ok(())
+    }
 
+    fn save(&self, writer: &mut impl Write) -> Result<(), Chip8Error> {
+        for page in self.pages.iter() {
+            writer.write_all(&page[..])?;
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, reader: &mut impl Read) -> Result<(), Chip8Error> {
+        for page in self.pages.iter_mut() {
+            reader.read_exact(Arc::make_mut(page).as_mut_slice())?;
+        }
         Ok(())
     }
 }
 
 /// Stack
+#[derive(Clone)]
 struct Stack {
     cells: [StackValue; STACK_SIZE],
     sp: StackAdress
@@ -204,10 +274,10 @@ impl Stack {
         }
     }
 
-    fn push(&mut self, value: StackValue) -> Result<(), &'static str> {
+    fn push(&mut self, value: StackValue) -> Result<(), Chip8Error> {
         
         if self.sp == STACK_SIZE {
-            return Err("Stack overflow")
+            return Err(Chip8Error::StackOverflow)
         }
         
         self.cells[self.sp] = value;
@@ -216,19 +286,20 @@ impl Stack {
         Ok(())
     }
 
-    fn pop(&mut self) -> Result<StackValue, &'static str> {
+    fn pop(&mut self) -> Result<StackValue, Chip8Error> {
 
         if self.sp == 0 {
-            return Err("Stack is empty, can't pop")
+            return Err(Chip8Error::StackUnderflow)
         }
 
         self.sp -= 1 ;
-        Ok(self.cells[self.sp-1])
+        Ok(self.cells[self.sp])
     }
 }
 
 /// Delay Timer
-struct DelayTimer {
+#[derive(Clone)]
+pub struct DelayTimer {
     value: u8
 }
 
@@ -238,25 +309,26 @@ impl DelayTimer {
         DelayTimer{value:0}
     }
     
-    fn decrease(&mut self) -> Result<(), &'static str> {
+    fn decrease(&mut self) -> Result<(), Chip8Error> {
         if self.value != 0 {
             self.value -= 1;
         }
         Ok(())
     }
 
-    fn get(&self) -> Result<u8, &'static str> {
+    pub fn get(&self) -> Result<u8, Chip8Error> {
         Ok(self.value)
     }
 
-    fn set(&mut self, value: u8) -> Result<(), &'static str> {
+    fn set(&mut self, value: u8) -> Result<(), Chip8Error> {
         self.value = value;
         Ok(())
     }
 }
 
 /// Sound Timer
-struct SoundTimer{
+#[derive(Clone)]
+pub struct SoundTimer{
     value: u8
 }
 
@@ -266,67 +338,123 @@ impl SoundTimer {
         SoundTimer{value:0}
     }
 
-    fn decrease(&mut self) -> Result<(), &'static str> {
+    fn decrease(&mut self) -> Result<(), Chip8Error> {
         if self.value != 0 {
             self.value -= 1;
         }
         Ok(())
     }
 
-    fn set(&mut self, value: u8) -> Result<(), &'static str> {
+    fn set(&mut self, value: u8) -> Result<(), Chip8Error> {
         self.value = value;
         Ok(())
     }
 
-    fn get(&self) -> Result<u8, &'static str> {
+    pub fn get(&self) -> Result<u8, Chip8Error> {
         Ok(self.value)
     }
 
 }
 
 /// FrameBuffer
-struct FrameBuffer{
-    buffer: [[u8; FRAME_BUFFER_LENGTH]; FRAME_BUFFER_HEIGHT]
+/// Indexed as (row, column), a non zero cell is a lit pixel.
+/// Starts in the 64x32 CHIP-8 resolution and can be switched to 128x64 by SUPER-CHIP programs
+#[derive(Clone)]
+pub struct FrameBuffer{
+    width: usize,
+    height: usize,
+    buffer: Vec<u8>
 }
 
 impl FrameBuffer {
     
     fn new() -> FrameBuffer {
         FrameBuffer {
-            buffer: [[0; FRAME_BUFFER_LENGTH]; FRAME_BUFFER_HEIGHT]
+            width: FRAME_BUFFER_LENGTH,
+            height: FRAME_BUFFER_HEIGHT,
+            buffer: vec![0; FRAME_BUFFER_LENGTH * FRAME_BUFFER_HEIGHT]
         }
     }
 
-    fn write(&mut self, i: usize, j:usize, value:u8) -> Result<(), &'static str> {
-        self.buffer[i][j] = value ;
+    fn write(&mut self, i: usize, j:usize, value:u8) -> Result<(), Chip8Error> {
+        if i >= self.height || j >= self.width {
+            return Err(Chip8Error::PixelOutOfRange { row: i, column: j })
+        }
+        self.buffer[i * self.width + j] = value ;
         Ok(())
     }
 
-    fn read(&self, i: usize, j:usize) -> Result<u8, &'static str>{
-        Ok(self.buffer[i][j])
+    pub fn read(&self, i: usize, j:usize) -> Result<u8, Chip8Error>{
+        if i >= self.height || j >= self.width {
+            return Err(Chip8Error::PixelOutOfRange { row: i, column: j })
+        }
+        Ok(self.buffer[i * self.width + j])
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_FRAME_BUFFER_LENGTH
+    }
+
+    /// Iterates over the rows, top to bottom
+    pub fn rows(&self) -> std::slice::Chunks<'_, u8> {
+        self.buffer.chunks(self.width)
+    }
+
+    /// Only switches off the given planes
+    fn clear(&mut self, planes: u8) {
+        self.buffer.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    /// Changing the resolution clears the screen
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.buffer = vec![0; width * height];
     }
 
-    fn full_image(&self) -> Result<&[[u8; FRAME_BUFFER_LENGTH]; FRAME_BUFFER_HEIGHT], &'static str> {
-        Ok(&self.buffer)
+    /// Moves the given planes by (dx, dy), pixels leaving the screen are lost
+    fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let old = self.buffer.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (src_x, src_y) = (x as isize - dx, y as isize - dy);
+                let inside = src_x >= 0 && src_y >= 0
+                    && (src_x as usize) < self.width && (src_y as usize) < self.height;
+                let moved = if inside { old[src_y as usize * self.width + src_x as usize] & planes } else { 0 };
+                let pixel = &mut self.buffer[y * self.width + x];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
     }
 }
 
 /// PC
+#[derive(Clone)]
 struct ProgramCounter {
-    register: Addr
+    register: Addr,
+    memory_size: usize
 }
 
 impl ProgramCounter {
 
-    fn new() -> ProgramCounter {
+    fn new(memory_size: usize) -> ProgramCounter {
         ProgramCounter {
-            register: PROGRAM_START
+            register: PROGRAM_START,
+            memory_size
         }
     }
 
-    fn incr(&mut self) -> Result<(), &'static str> {
-        if self.register == MEMORY_SIZE - 1 {
-            return Err("Program Counter Overflow")
+    fn incr(&mut self) -> Result<(), Chip8Error> {
+        if self.register + 2 >= self.memory_size {
+            return Err(Chip8Error::ProgramCounterOutOfRange { addr: self.register + 2 })
         }
 
         self.register += 2 ;
@@ -334,10 +462,10 @@ impl ProgramCounter {
         Ok(())
     }
 
-    fn change(&mut self, addr: Addr) -> Result<(), &'static str> {
+    fn change(&mut self, addr: Addr) -> Result<(), Chip8Error> {
 
-        if addr >= MEMORY_SIZE {
-            return Err("Trying to set program counter to code out of the memory")
+        if addr >= self.memory_size {
+            return Err(Chip8Error::ProgramCounterOutOfRange { addr })
         }
 
         self.register = addr ;
@@ -351,6 +479,7 @@ impl ProgramCounter {
 }
 
 /// I
+#[derive(Clone)]
 struct IndexRegister{
     value: Addr
 }
@@ -361,24 +490,28 @@ impl IndexRegister {
         IndexRegister{value:0}
     }
 
-    fn set(&mut self, value: Addr) -> Result<(), &'static str> {
+    fn set(&mut self, value: Addr) -> Result<(), Chip8Error> {
         self.value = value;
         Ok(())
     }
 
-    fn get(&self) -> Result<Addr, &'static str> {
+    fn get(&self) -> Result<Addr, Chip8Error> {
         Ok(self.value)
     }
 }
 
+#[derive(Clone)]
 enum CpuState {
     IDLE,
     FETCH,
     DECODE,
-    EXEC
+    EXEC,
+    /// Fx0A is waiting for a key to be pressed then released, Vx holds the index
+    WAIT_KEY(VIndex)
 }
 
 /// CPU
+#[derive(Clone)]
 pub struct CPU {
     on: bool,
     ram: Memory,
@@ -390,376 +523,652 @@ pub struct CPU {
     st: SoundTimer,
     frame_buff: FrameBuffer,
     keyboard: u16,
+    key_down: Option<u8>,
+    planes: u8,
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
+    rpl: [VValue; RPL_FLAGS_COUNT],
+    font_address: Addr,
+    quirks: Quirks,
+    vblank: bool,
+    display_wait: bool,
     frequency: u32,
-    frequency_counter: u32,
+    /// 60 per cycle run by run_cycles, a timers tick being due every `frequency`
+    timer_accumulator: u32,
+    cycles: u64,
     opcode: u16,
     instr: Option<Instruction>,
     state: CpuState,
     refresh: bool,
-    sound: bool
+    random: Random,
+    trace_hook: TraceHook
+}
+
+/// Machine state captured by CPU::snapshot
+#[derive(Clone)]
+pub struct Snapshot {
+    cpu: CPU
 }
 
 impl CPU {
 
-    pub fn new(frequency: Option<u32>) -> CPU {
+    pub fn new(frequency: Option<u32>, quirks: Quirks) -> CPU {
 
         let frequency = match frequency {
             Some(frequency) => frequency,
             None => DEFAULT_FREQUENCY
         };
 
+        let memory_size = if quirks.extended_memory { EXTENDED_MEMORY_SIZE } else { MEMORY_SIZE };
+
         CPU {
             on: false,
-            ram: Memory::new(),
+            ram: Memory::new(memory_size),
             v: Registers::new(),
             stack: Stack::new(),
-            pc: ProgramCounter::new(),
+            pc: ProgramCounter::new(memory_size),
             index_register: IndexRegister::new(),
             dt: DelayTimer::new(),
             st: SoundTimer::new(),
             frame_buff: FrameBuffer::new(),
             keyboard: 0,
-            frequency: frequency,
-            frequency_counter: 0u32,
+            key_down: None,
+            planes: 1,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            rpl: [0; RPL_FLAGS_COUNT],
+            font_address: DEFAULT_FONT_ADDRESS,
+            quirks,
+            vblank: false,
+            display_wait: false,
+            frequency,
+            timer_accumulator: 0,
+            cycles: 0,
             opcode: 0,
             instr: None,
             state: CpuState::IDLE,
             refresh: false,
-            sound: false
+            random: Random::seeded(rand::random()),
+            trace_hook: TraceHook::default()
         }
     }
 
     pub fn power_on(&mut self) {
         self.on = true;
-        let mut base_addr = 0 as usize;
-        for chr in FONT_SET{
-            self.ram.write(base_addr, chr).unwrap();
-            base_addr += 1;
+        for (offset, chr) in FONT_SET.iter().chain(BIG_FONT_SET.iter()).enumerate() {
+            self.ram.write(self.font_address + offset, *chr).unwrap();
         }
     }
 
-    pub fn loadt(&mut self, filename: &str) -> Result<(), &'static str>{
-        let content = fs::read_to_string(filename).unwrap();
-        let mut i = PROGRAM_START ;
-        for line in content.lines() {
-            let opcode = i64::from_str_radix(line, 16).unwrap() as u16;
-            let l = ((opcode & 0xFF00) >> 8) as CellValue;
-            let r = (opcode & 0x00FF) as CellValue;
-            self.ram.write(i, l)?;
-            self.ram.write(i+1, r)?;
-            i += 2;
+    /// Where the built-in fonts are copied by power_on, the big font right after the small one.
+    /// Both must fit below the program
+    pub fn set_font_address(&mut self, addr: Addr) -> Result<(), Chip8Error> {
+        if addr + FONT_SET.len() + BIG_FONT_SET.len() > PROGRAM_START {
+            return Err(Chip8Error::InvalidFontAddress { addr })
         }
+        self.font_address = addr;
         Ok(())
     }
 
-    pub fn loadb(&mut self, filename: &str) -> Result<(), &'static str>{
-        let mut f = File::open(&filename).expect("no file found");
-        let metadata = fs::metadata(&filename).expect("unable to read metadata");
-        let mut buffer = vec![0; metadata.len() as usize];
-        f.read(&mut buffer).expect("buffer overflow");
-        let mut addr = PROGRAM_START;
-        for chunk in buffer {
-            self.ram.write(addr, chunk)?;
-            addr += 1; 
-        }
-        
-        Ok(())
+    pub fn font_address(&self) -> Addr {
+        self.font_address
     }
 
-    pub fn consume_refresh(&mut self) -> Result<bool, &'static str>{
-        if self.refresh {
-            self.refresh = false;
-            Ok(true)
-        }
-        else{
-            Ok(false)
-        }
+    pub fn big_font_address(&self) -> Addr {
+        self.font_address + FONT_SET.len()
     }
 
-    fn set_refresh(&mut self) -> Result<(), &'static str> {
-        self.refresh = true;
-        Ok(())
+    /// False once the program stopped the interpreter with 00FD
+    pub fn is_on(&self) -> bool {
+        self.on
     }
 
-    pub fn sound(&self) -> Result<bool, &'static str> {
-        Ok(self.sound)
+    pub fn rpl_flags(&self) -> &[VValue; RPL_FLAGS_COUNT] {
+        &self.rpl
     }
 
-    pub fn  get_image(&self) -> Result<&[[u8; FRAME_BUFFER_LENGTH]; FRAME_BUFFER_HEIGHT], &'static str> {
-        self.frame_buff.full_image()
+    pub fn memory_size(&self) -> usize {
+        self.ram.size()
     }
 
-    pub fn next_cycle(&mut self) -> Result<(), &'static str> {
-
-        if !self.on {
-            self.state = CpuState::IDLE ;
-        }
-        else {
-            match self.state {
-            
-                CpuState::IDLE => {
-                    self.state = CpuState::FETCH ;
-                },
+    /// Bit planes Dxyn, 00E0 and scrolls currently work on
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
 
-                CpuState::FETCH => {
-                    self.state = CpuState::DECODE ;
-                },
-                
-                CpuState::DECODE => {
-                    self.state = CpuState::EXEC;
-                },
-                
-                CpuState::EXEC => {
-                    self.state = CpuState::FETCH;
-                }
-            }
-        }
+    /// 1-bit samples played, most significant bit first, while the sound timer is active
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
 
-        Ok(())
+    pub fn pitch(&self) -> u8 {
+        self.pitch
     }
 
-    pub fn simulate(&mut self) -> Result<(), &'static str> {
+    /// Samples of the audio pattern per second, 4000 at the default pitch
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
 
-        let mut simul_cycles: u64 = 1;
-        
-        //Perform state action
-        match self.state {
-            CpuState::IDLE => {},
-            
-            CpuState::FETCH => {
-                // println!("[+] fetching @{}", self.pc.get());
-                self.fetch()?;
-            }
+    /// Instructions per second given to new()
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
 
-            CpuState::DECODE => {
-                // println!("[+] decoding #{}", self.opcode);
-                self.decode()?;
-            }
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
 
-            CpuState::EXEC => {
-                // if let Some(instr) = &self.instr {
-                // println!("[+] ccrt_instr: {:?}", instr);
-                //}
-                simul_cycles = self.execute()?;
-            }
-        };
+    /// Largest ROM that fits between PROGRAM_START and the end of the memory
+    pub fn max_rom_size(&self) -> usize {
+        self.ram.size() - PROGRAM_START
+    }
 
-        let sleep_time = &simul_cycles * (1e6 as f64 / (self.frequency as f64)) as u64 ;
+    /// Copies a binary ROM at PROGRAM_START
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        if rom.len() > self.max_rom_size() {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max: self.max_rom_size() })
+        }
+        for (offset, byte) in rom.iter().enumerate() {
+            self.ram.write(PROGRAM_START + offset, *byte)?;
+        }
+        Ok(())
+    }
 
-        //Update the frequency counter for delay and sound registers
-        self.frequency_counter = (self.frequency_counter + simul_cycles as u32) % (self.frequency / 60) ;
+    pub fn load_reader(&mut self, mut reader: impl Read) -> Result<(), Chip8Error> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        self.load_rom(&rom)
+    }
 
-        if self.frequency_counter == 0 {
-            for _ in 0..simul_cycles {
-                self.st.decrease()?;
-                self.dt.decrease()?;
+    /// Loads a program written as one hexadecimal opcode per line, blank lines are ignored
+    pub fn load_text(&mut self, text: &str) -> Result<(), Chip8Error> {
+        let mut rom = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
             }
+            let opcode = u16::from_str_radix(line, 16).map_err(|_| Chip8Error::InvalidHexLine {
+                line: number + 1,
+                content: line.to_string()
+            })?;
+            rom.extend_from_slice(&opcode.to_be_bytes());
         }
+        self.load_rom(&rom)
+    }
 
-        if self.st.get()? != 0 {
-            self.sound = true;
-        }
-        else {
-            self.sound = false;
-        }
+    /// Assembles `source` with crate::asm and loads the result
+    pub fn load_assembly(&mut self, source: &str) -> Result<(), Chip8Error> {
+        self.load_rom(&asm::assemble(source)?)
+    }
 
-        sleep(Duration::from_micros(sleep_time));
+    pub fn loada(&mut self, filename: impl AsRef<Path>) -> Result<(), Chip8Error>{
+        self.load_rom(&asm::assemble_file(filename)?)
+    }
 
-        Ok(())
+    pub fn loadt(&mut self, filename: impl AsRef<Path>) -> Result<(), Chip8Error>{
+        let content = fs::read_to_string(filename)?;
+        self.load_text(&content)
+    }
 
+    pub fn loadb(&mut self, filename: impl AsRef<Path>) -> Result<(), Chip8Error>{
+        self.load_reader(File::open(filename)?)
     }
 
-    fn fetch(&mut self) -> Result<(), &'static str> {
-        let pc_value = self.pc.get();
-        let l = self.ram.read(pc_value)? as u16;
-        let r = self.ram.read(pc_value + 1)? as u16;
-        self.opcode = (l<<8)+ r;
-        Ok(())  
+    /// Decodes the instruction stored at `addr`, including the address following F000
+    pub fn instruction_at(&self, addr: Addr) -> Result<Instruction, Chip8Error> {
+        let opcode = ((self.ram.read(addr)? as u16) << 8) | self.ram.read(addr + 1)? as u16;
+        match Instruction::decode(opcode) {
+            Ok(Instruction::LD_I_LONG(_)) => {
+                let long = ((self.ram.read(addr + 2)? as Addr) << 8) | self.ram.read(addr + 3)? as Addr;
+                Ok(Instruction::LD_I_LONG(long))
+            }
+            Ok(instr) => Ok(instr),
+            Err(_) => Err(Chip8Error::IllegalOpcode { opcode, pc: addr })
+        }
     }
 
-    fn decode(&mut self) -> Result<(), &'static str> {
-        
-        let bytes = (
-            ((self.opcode & 0xF000) >> 12)as u8,
-            ((self.opcode & 0x0F00) >> 8)as u8,
-            ((self.opcode & 0x00F0) >> 4)as u8,
-            (self.opcode & 0x000F) as u8
-        );
+    pub fn read_memory(&self, addr: Addr) -> Result<CellValue, Chip8Error> {
+        self.ram.read(addr)
+    }
 
-        match bytes {
+    /// Return addresses currently on the stack, the most recent last
+    pub fn stack(&self) -> &[StackValue] {
+        &self.stack.cells[..self.stack.sp]
+    }
 
-            (0, 0, 0xE, 0) => {
-                self.instr = Some(Instruction::CLS) ;
-            },
+    /// In memory copy of the whole machine, cheaper than a save state
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { cpu: self.clone() }
+    }
 
-            (0,0,0xE, 0xE) => {
-                self.instr = Some(Instruction::RET) ;
-            },
+    /// Puts the machine back in the state it had when the snapshot was taken, the trace hook is kept
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let trace_hook = std::mem::take(&mut self.trace_hook);
+        self.clone_from(&snapshot.cpu);
+        self.trace_hook = trace_hook;
+    }
 
-            (1,_,_,_) => {
-                let addr = (self.opcode & 0x0FFF) as Addr;
-                self.instr = Some(Instruction::JP(addr));
-            },
+    /// Writes the whole machine, little endian, after SAVE_STATE_MAGIC and SAVE_STATE_VERSION
+    pub fn save_state(&self, writer: &mut impl Write) -> Result<(), Chip8Error> {
+        writer.write_all(&SAVE_STATE_MAGIC)?;
+        writer.write_all(&[SAVE_STATE_VERSION, self.quirks.to_bits(), self.on as u8])?;
+        writer.write_all(&self.frequency.to_le_bytes())?;
+
+        writer.write_all(&(self.ram.size() as u32).to_le_bytes())?;
+        self.ram.save(writer)?;
+        writer.write_all(&self.v.regs)?;
+        for cell in self.stack.cells.iter() {
+            writer.write_all(&(*cell as u32).to_le_bytes())?;
+        }
+        writer.write_all(&[self.stack.sp as u8])?;
+        writer.write_all(&(self.pc.get() as u32).to_le_bytes())?;
+        writer.write_all(&(self.index_register.get()? as u32).to_le_bytes())?;
+        writer.write_all(&[self.dt.get()?, self.st.get()?])?;
+
+        writer.write_all(&(self.frame_buff.width as u16).to_le_bytes())?;
+        writer.write_all(&(self.frame_buff.height as u16).to_le_bytes())?;
+        writer.write_all(&self.frame_buff.buffer)?;
+
+        writer.write_all(&self.keyboard.to_le_bytes())?;
+        writer.write_all(&[self.key_down.unwrap_or(NO_KEY), self.planes])?;
+        writer.write_all(&self.audio_pattern)?;
+        writer.write_all(&[self.pitch])?;
+        writer.write_all(&self.rpl)?;
+        writer.write_all(&(self.font_address as u32).to_le_bytes())?;
+        writer.write_all(&[self.vblank as u8, self.display_wait as u8, self.refresh as u8])?;
+        writer.write_all(&self.timer_accumulator.to_le_bytes())?;
+        writer.write_all(&self.cycles.to_le_bytes())?;
+        writer.write_all(&self.opcode.to_le_bytes())?;
+        let (state, vx) = match self.state {
+            CpuState::IDLE => (0, 0),
+            CpuState::FETCH => (1, 0),
+            CpuState::DECODE => (2, 0),
+            CpuState::EXEC => (3, 0),
+            CpuState::WAIT_KEY(vx) => (4, vx as u8)
+        };
+        writer.write_all(&[state, vx])?;
+        writer.write_all(&[self.random.seed().is_some() as u8])?;
+        writer.write_all(&self.random.seed().unwrap_or(0).to_le_bytes())?;
+        writer.write_all(&self.random.draws().to_le_bytes())?;
+        Ok(())
+    }
 
-            (2,_,_,_) => {
-                let addr = (self.opcode & 0x0FFF) as Addr;
-                self.instr = Some(Instruction::CALL(addr));
-            },
+    /// Replaces the whole machine, quirks and frequency included, by a state written by save_state.
+    /// The trace hook is kept, and so is the random source when the saved one was set by the host.
+    /// The CPU is left untouched when the state can't be read
+    pub fn load_state(&mut self, mut reader: impl Read) -> Result<(), Chip8Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != SAVE_STATE_MAGIC {
+            return Err(Chip8Error::InvalidSaveState("not a CHIP-8 save state".to_string()))
+        }
+        let version = read_u8(&mut reader)?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Chip8Error::UnsupportedSaveStateVersion { version })
+        }
+        let quirks = Quirks::from_bits(read_u8(&mut reader)?);
+        let on = read_u8(&mut reader)? != 0;
+        let mut cpu = CPU::new(Some(read_u32(&mut reader)?), quirks);
+        cpu.on = on;
+
+        let memory_size = read_u32(&mut reader)? as usize;
+        if memory_size != cpu.ram.size() {
+            return Err(Chip8Error::InvalidSaveState(format!("{} bytes of memory do not match the quirks", memory_size)))
+        }
+        cpu.ram.load(&mut reader)?;
+        reader.read_exact(&mut cpu.v.regs)?;
+        for cell in cpu.stack.cells.iter_mut() {
+            *cell = read_u32(&mut reader)? as StackValue;
+        }
+        cpu.stack.sp = read_u8(&mut reader)? as StackAdress;
+        if cpu.stack.sp > STACK_SIZE {
+            return Err(Chip8Error::InvalidSaveState(format!("stack pointer {} is past the stack", cpu.stack.sp)))
+        }
+        let pc = read_u32(&mut reader)? as Addr;
+        cpu.pc.change(pc).map_err(|_| Chip8Error::InvalidSaveState(format!("program counter {:#05X} is out of the memory", pc)))?;
+        cpu.index_register.set(read_u32(&mut reader)? as Addr)?;
+        cpu.dt.set(read_u8(&mut reader)?)?;
+        cpu.st.set(read_u8(&mut reader)?)?;
+
+        let width = read_u16(&mut reader)? as usize;
+        let height = read_u16(&mut reader)? as usize;
+        if (width, height) != (FRAME_BUFFER_LENGTH, FRAME_BUFFER_HEIGHT)
+            && (width, height) != (HIRES_FRAME_BUFFER_LENGTH, HIRES_FRAME_BUFFER_HEIGHT) {
+            return Err(Chip8Error::InvalidSaveState(format!("{}x{} is not a CHIP-8 resolution", width, height)))
+        }
+        cpu.frame_buff.resize(width, height);
+        reader.read_exact(&mut cpu.frame_buff.buffer)?;
+
+        cpu.keyboard = read_u16(&mut reader)?;
+        cpu.key_down = match read_u8(&mut reader)? {
+            NO_KEY => None,
+            key if (key as usize) < KEY_COUNT => Some(key),
+            key => return Err(Chip8Error::InvalidKey { key })
+        };
+        cpu.planes = read_u8(&mut reader)? & PLANES_MASK;
+        reader.read_exact(&mut cpu.audio_pattern)?;
+        cpu.pitch = read_u8(&mut reader)?;
+        reader.read_exact(&mut cpu.rpl)?;
+        cpu.set_font_address(read_u32(&mut reader)? as Addr)?;
+        cpu.vblank = read_u8(&mut reader)? != 0;
+        cpu.display_wait = read_u8(&mut reader)? != 0;
+        cpu.refresh = read_u8(&mut reader)? != 0;
+        cpu.timer_accumulator = read_u32(&mut reader)?;
+        cpu.cycles = read_u64(&mut reader)?;
+        cpu.opcode = read_u16(&mut reader)?;
+        cpu.state = match (read_u8(&mut reader)?, read_u8(&mut reader)? as VIndex) {
+            (0, _) => CpuState::IDLE,
+            (1, _) => CpuState::FETCH,
+            (2, _) => CpuState::DECODE,
+            (3, _) => CpuState::EXEC,
+            (4, vx) if vx < GP_REGISTERS_COUNT => CpuState::WAIT_KEY(vx),
+            (state, _) => return Err(Chip8Error::InvalidSaveState(format!("unknown CPU state {}", state)))
+        };
+        let seeded = read_u8(&mut reader)? != 0;
+        let (seed, draws) = (read_u64(&mut reader)?, read_u64(&mut reader)?);
+        cpu.random = if seeded { Random::resumed(seed, draws) } else { self.random.clone() };
 
-            (3,x,_,_) => {
-                let k = (self.opcode & 0x00FF) as VValue;  
-                self.instr = Some(Instruction::SEi(x as VIndex, k));
-            },
+        cpu.trace_hook = std::mem::take(&mut self.trace_hook);
+        *self = cpu;
+        Ok(())
+    }
 
-            (4,x,_,_) => {
-                let kk = (self.opcode & 0x00FF) as VValue;  
-                self.instr = Some(Instruction::SNEi(x as VIndex, kk));  
-            },
+    pub fn consume_refresh(&mut self) -> Result<bool, Chip8Error>{
+        if self.refresh {
+            self.refresh = false;
+            Ok(true)
+        }
+        else{
+            Ok(false)
+        }
+    }
 
-            (5,x,y,0) => {
-                self.instr = Some(Instruction::SE(x as VIndex,y as VIndex));
-            },
+    fn set_refresh(&mut self) -> Result<(), Chip8Error> {
+        self.refresh = true;
+        Ok(())
+    }
 
-            (6,x,_,_) => {
-                let kk = (self.opcode & 0x00FF) as VValue; 
-                self.instr = Some(Instruction::LDi(x as VIndex, kk));
-            },
+    /// Opcode of the instruction being executed, useful to report errors
+    pub fn current_opcode(&self) -> u16 {
+        self.opcode
+    }
 
-            (7,x,_,_) => {
-                let kk = (self.opcode & 0x00FF) as VValue; 
-                self.instr = Some(Instruction::ADDi(x as VIndex, kk));
-            },
+    pub fn sound(&self) -> Result<bool, Chip8Error> {
+        Ok(self.st.get()? != 0)
+    }
 
-            (8,x,y,0) => {
-                self.instr = Some(Instruction::LD(x as VIndex,y as VIndex));
-            },
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.frame_buff
+    }
 
-            (8,x,y,1) => {
-                self.instr = Some(Instruction::OR(x as VIndex,y as VIndex));
-            },
+    pub fn delay_timer(&self) -> &DelayTimer {
+        &self.dt
+    }
 
-            (8,x,y,2) => {
-                self.instr = Some(Instruction::AND(x as VIndex,y as VIndex));
-            },
+    pub fn sound_timer(&self) -> &SoundTimer {
+        &self.st
+    }
 
-            (8,x,y,3) => {
-                self.instr = Some(Instruction::XOR(x as VIndex,y as VIndex));
-            },
+    pub fn register(&self, index: VIndex) -> VValue {
+        self.v.regs[index % GP_REGISTERS_COUNT]
+    }
 
-            (8,x,y,4) => {
-                self.instr = Some(Instruction::ADD(x as VIndex,y as VIndex));
-            },
+    pub fn program_counter(&self) -> Addr {
+        self.pc.get()
+    }
 
-            (8,x,y,5) => {
-                self.instr = Some(Instruction::SUB(x as VIndex,y as VIndex));
-            },
+    pub fn index_register(&self) -> Addr {
+        self.index_register.value
+    }
 
-            (8,x,_,6) => {
-                self.instr = Some(Instruction::SHR(x as VIndex));
-            },
+    /// Replace the whole keypad state, index i being CHIP-8 key i
+    pub fn set_keys(&mut self, keys: &[bool; KEY_COUNT]) {
+        self.keyboard = 0;
+        for (key, pressed) in keys.iter().enumerate() {
+            if *pressed {
+                self.keyboard |= 1 << key;
+            }
+        }
+    }
 
-            (8,x,y,7) => {
-                self.instr = Some(Instruction::SUBN(x as VIndex,y as VIndex));
-            },
+    pub fn press_key(&mut self, key: u8) -> Result<(), Chip8Error> {
+        if key as usize >= KEY_COUNT {
+            return Err(Chip8Error::InvalidKey { key })
+        }
+        self.keyboard |= 1 << key;
+        Ok(())
+    }
 
-            (8,x,_,0xE) => {
-                self.instr = Some(Instruction::SHL(x as VIndex));
-            },
+    pub fn release_key(&mut self, key: u8) -> Result<(), Chip8Error> {
+        if key as usize >= KEY_COUNT {
+            return Err(Chip8Error::InvalidKey { key })
+        }
+        self.keyboard &= !(1 << key);
+        Ok(())
+    }
 
-            (9,x,y,0) => {
-                self.instr = Some(Instruction::SNE(x as VIndex,y as VIndex));
-            },
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        (key as usize) < KEY_COUNT && self.keyboard & (1 << key) != 0
+    }
 
-            (0xA,_,_,_) => {
-                let addr = (self.opcode & 0x0FFF) as Addr;
-                self.instr = Some(Instruction::LD_I(addr));
-            },
+    /// True while a Dxyn instruction waits for the next timers tick, see Quirks::display_wait
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.display_wait
+    }
 
-            (0xB,_,_,_) => {
-                let addr = (self.opcode & 0x0FFF) as Addr;
-                self.instr = Some(Instruction::JP_V0(addr));
-            },
+    /// True while an Fx0A instruction is blocking execution
+    pub fn is_waiting_for_key(&self) -> bool {
+        matches!(self.state, CpuState::WAIT_KEY(_))
+    }
 
-            (0xC,x,_,_) => {
-                let kk = (self.opcode & 0x00FF) as VValue; 
-                self.instr = Some(Instruction::RNDi(x as VIndex, kk));
-            },
+    /// Executes exactly one instruction. While Fx0A waits for a key, only checks the keypad
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if !self.on {
+            return Ok(())
+        }
 
-            (0xD,x,y,n) => {
-                self.instr = Some(Instruction::DRW(x as VIndex, y as VIndex, n));
-            },
+        if let CpuState::WAIT_KEY(vx) = self.state {
+            return self.wait_key(vx)
+        }
 
-            (0xE, x, 9, 0xE) => {
-                self.instr = Some(Instruction::SKP(x as VIndex));
-            },
+        self.display_wait = false;
+        let pc = self.pc.get();
+        self.state = CpuState::FETCH;
+        self.fetch()?;
+        self.state = CpuState::DECODE;
+        self.decode()?;
+        self.state = CpuState::EXEC;
+        self.execute()?;
+
+        // A draw waiting for the refresh is executed again later
+        if !self.display_wait {
+            self.cycles += 1;
+            if self.trace_hook.0.is_some() {
+                self.trace(pc);
+            }
+        }
 
-            (0xE, x, 0xA, 1) => {
-                self.instr = Some(Instruction::SKNP(x as VIndex));
-            },
+        // LD_K leaves the CPU in WAIT_KEY
+        if let CpuState::EXEC = self.state {
+            self.state = CpuState::IDLE;
+        }
+        Ok(())
+    }
 
-            (0xF, x, 0, 7) => {
-                self.instr = Some(Instruction::LD_DT(x as VIndex));
-            },
+    /// Instructions executed since the CPU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
 
-            (0xF, x, 0, 0xA) => {
-                self.instr = Some(Instruction::LD_K(x as VIndex));
-            },
+    /// Seed of the random source, None when the host replaced it with set_random_source
+    pub fn seed(&self) -> Option<u64> {
+        self.random.seed()
+    }
 
-            (0xF, x, 1, 5) => {
-                self.instr = Some(Instruction::SET_DT(x as VIndex));
-            },
+    /// Restarts Cxnn random bytes from `seed`, the same seed giving the same bytes
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::seeded(seed);
+    }
 
-            (0xF, x, 1, 8) => {
-                self.instr = Some(Instruction::SET_ST(x as VIndex));
-            },
+    /// Draws Cxnn random bytes from `source` instead of the seeded generator
+    pub fn set_random_source(&mut self, source: impl RandomSource + 'static) {
+        self.random = Random::custom(Box::new(source));
+    }
 
-            (0xF, x, 1, 0xE) => {
-                self.instr = Some(Instruction::ADD_I(x as VIndex));
-            },
+    /// Calls `hook` after every instruction executed by step, replacing the previous hook
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TraceEntry) + Send + 'static) {
+        self.trace_hook = TraceHook(Some(Box::new(hook)));
+    }
 
-            (0xF, x, 2, 9) => {
-                self.instr = Some(Instruction::LD_F(x as VIndex));
-            },
+    /// Stops tracing, dropping the hook
+    pub fn remove_trace_hook(&mut self) {
+        self.trace_hook = TraceHook(None);
+    }
 
-            (0xF, x, 3, 3) => {
-                self.instr = Some(Instruction::LD_B(x as VIndex));
+    fn trace(&mut self, pc: Addr) {
+        let entry = TraceEntry {
+            cycle: self.cycles,
+            pc,
+            opcode: self.opcode,
+            instruction: match self.instr {
+                Some(instr) => instr,
+                None => return,
             },
+            v: self.v.regs,
+            i: self.index_register.value,
+            sp: self.stack.sp,
+            dt: self.dt.value,
+            st: self.st.value,
+        };
+        if let Some(hook) = self.trace_hook.0.as_mut() {
+            hook(&entry);
+        }
+    }
 
-            (0xF, x, 5, 5) => {
-                self.instr = Some(Instruction::ST_UNTIL(x as VIndex));
-            },
+    /// Runs one 60th of a second: up to `cycles` instructions, then a timers tick.
+    /// The frame ends early when a draw has to wait for the refresh
+    pub fn run_frame(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            self.step()?;
+            if self.is_waiting_for_vblank() {
+                break;
+            }
+        }
+        self.tick_timers()
+    }
 
-            (0xF, x, 6, 5) => {
-                self.instr = Some(Instruction::LD_UNTIL(x as VIndex));
-            },
+    /// Runs `cycles` instructions, every instruction lasting one cycle at the frequency
+    /// given to new(). Timers tick each time a 60th of a second of emulated time went by,
+    /// including the cycles run by previous calls
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            self.step()?;
+            self.timer_accumulator += 60;
+            while self.timer_accumulator >= self.frequency.max(1) {
+                self.timer_accumulator -= self.frequency.max(1);
+                self.tick_timers()?;
+            }
+        }
+        Ok(())
+    }
 
-            _ => {
-                return Err("Can't decode current instruction");
+    /// Instructions per 60Hz frame at the frequency given to new()
+    pub fn cycles_per_frame(&self) -> usize {
+        (self.frequency / 60).max(1) as usize
+    }
+
+    /// Decrements the delay and sound timers, to be called at 60Hz by hosts
+    /// that do not use run_frame or run_cycles
+    pub fn tick_timers(&mut self) -> Result<(), Chip8Error> {
+        self.vblank = true;
+        self.dt.decrease()?;
+        self.st.decrease()?;
+        Ok(())
+    }
+
+    /// Fx0A completes on the release of the first key pressed while waiting
+    fn wait_key(&mut self, vx: VIndex) -> Result<(), Chip8Error> {
+        match self.key_down {
+            None => {
+                if self.keyboard != 0 {
+                    self.key_down = Some(self.keyboard.trailing_zeros() as u8);
+                }
+            }
+            Some(key) => {
+                if !self.is_key_pressed(key) {
+                    self.v.write(vx, key)?;
+                    self.key_down = None;
+                    self.pc.incr()?;
+                    self.state = CpuState::IDLE;
+                }
             }
         }
-        
+        Ok(())
+    }
+
+    /// Skips the next instruction, F000 NNNN being 4 bytes long
+    fn skip_next(&mut self) -> Result<(), Chip8Error> {
+        let next = self.pc.get() + 2;
+        let long = matches!((self.ram.read(next), self.ram.read(next + 1)), (Ok(0xF0), Ok(0x00)));
+        self.pc.incr()?;
+        if long {
+            self.pc.incr()?;
+        }
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<(), Chip8Error> {
+        let pc_value = self.pc.get();
+        let l = self.ram.read(pc_value)? as u16;
+        let r = self.ram.read(pc_value + 1)? as u16;
+        self.opcode = (l<<8)+ r;
+        Ok(())  
+    }
 
+    fn decode(&mut self) -> Result<(), Chip8Error> {
+        self.instr = Some(self.instruction_at(self.pc.get())?);
         Ok(())
     }
 
-    fn execute(&mut self) -> Result<u64, &'static str> {
+    fn execute(&mut self) -> Result<(), Chip8Error> {
         
         let mut increase_pc = true ;
-        let mut cycles = 1;
 
         if let Some(instr) = &self.instr {
             
             match instr {
             
                 Instruction::SYS(_) => {
-                    return Err("SYS instruction no more supported");
+                    return Err(Chip8Error::IllegalOpcode { opcode: self.opcode, pc: self.pc.get() });
                 }
                 Instruction::CLS => {
-                    for i in 0..FRAME_BUFFER_HEIGHT {
-                        for j in 0..FRAME_BUFFER_LENGTH {
-                            self.frame_buff.write(i,j,0)?;
-                        }
-                    }
+                    self.frame_buff.clear(self.planes);
+                    self.set_refresh()?;
+                }
+                Instruction::SCD(n) => {
+                    self.frame_buff.scroll(0, *n as isize, self.planes);
+                    self.set_refresh()?;
+                }
+                Instruction::SCR => {
+                    self.frame_buff.scroll(HORIZONTAL_SCROLL as isize, 0, self.planes);
+                    self.set_refresh()?;
+                }
+                Instruction::SCL => {
+                    self.frame_buff.scroll(-(HORIZONTAL_SCROLL as isize), 0, self.planes);
+                    self.set_refresh()?;
+                }
+                Instruction::EXIT => {
+                    self.on = false;
+                    increase_pc = false;
+                }
+                Instruction::LOW => {
+                    self.frame_buff.resize(FRAME_BUFFER_LENGTH, FRAME_BUFFER_HEIGHT);
+                    self.set_refresh()?;
+                }
+                Instruction::HIGH => {
+                    self.frame_buff.resize(HIRES_FRAME_BUFFER_LENGTH, HIRES_FRAME_BUFFER_HEIGHT);
                     self.set_refresh()?;
                 }
                 Instruction::RET => {
@@ -779,17 +1188,17 @@ impl CPU {
                 },
                 Instruction::SEi(vx, kk) => {
                     if self.v.read(*vx)? == *kk {
-                        self.pc.incr()?; 
+                        self.skip_next()?;
                     }
                 },
                 Instruction::SNEi(vx, kk) => {
                     if self.v.read(*vx)? != *kk {
-                        self.pc.incr()?; 
+                        self.skip_next()?;
                     }
                 },
                 Instruction::SE(vx, vy) => {
                     if self.v.read(*vx)? == self.v.read(*vy)? {
-                        self.pc.incr()?; 
+                        self.skip_next()?;
                     }
                 }
                 Instruction::LDi(vx,kk) => {
@@ -797,11 +1206,11 @@ impl CPU {
                 }
                 Instruction::ADDi(vx,kk) => {
                     let v = self.v.read(*vx)?;
-                    self.v.write(*vx, v+*kk)?;
+                    self.v.write(*vx, v.wrapping_add(*kk))?;
                 }
                 Instruction::SNE(vx,vy) => {
                     if self.v.read(*vx)? != self.v.read(*vy)? {
-                        self.pc.incr()?; 
+                        self.skip_next()?;
                     }
                 }
                 Instruction::OR(vx,vy) => {
@@ -809,68 +1218,58 @@ impl CPU {
                     let y = self.v.read(*vy)?;
                     let x = x | y ;
                     self.v.write(*vx, x)?;
+                    if self.quirks.vf_reset {
+                        self.v.set_f(false)?;
+                    }
                 }
                 Instruction::AND(vx,vy) => {
                     let x = self.v.read(*vx)?;
                     let y = self.v.read(*vy)?;
                     let x = x & y ;
                     self.v.write(*vx, x)?;
+                    if self.quirks.vf_reset {
+                        self.v.set_f(false)?;
+                    }
                 }
                 Instruction::XOR(vx,vy) => {
                     let x = self.v.read(*vx)?;
                     let y = self.v.read(*vy)?;
                     let x = x ^ y ;
                     self.v.write(*vx, x)?;
+                    if self.quirks.vf_reset {
+                        self.v.set_f(false)?;
+                    }
                 }
                 Instruction::ADD(vx,vy) => {
-                    self.v.clr_f()?;
                     let x = self.v.read(*vx)?;
                     let y = self.v.read(*vy)?;
-                    let result = x as u16 + y as u16;
-                    self.v.write(*vx, (result & 0xFF) as CellValue)?;
-                    if result > 0xFF {
-                        self.v.set_f()?;
-                    }
+                    let (result, carry) = x.overflowing_add(y);
+                    self.v.write(*vx, result)?;
+                    self.v.set_f(carry)?;
                 }
                 Instruction::SUB(vx,vy) => {
-                    self.v.clr_f()?;
-                    let mut x = self.v.read(*vx)?;
-                    let mut y = self.v.read(*vy)?;
-                    if x <= y{
-                    }
-                    else {
-                        self.v.set_f()?;
-                    }
-                    let result = x as u16 - y as u16;
-                    self.v.write(*vx, (result & 0xFF) as CellValue)?;
+                    let x = self.v.read(*vx)?;
+                    let y = self.v.read(*vy)?;
+                    let (result, borrow) = x.overflowing_sub(y);
+                    self.v.write(*vx, result)?;
+                    self.v.set_f(!borrow)?;
                 }
-                Instruction::SHR(vx) => {
-                    let mut x = self.v.read(*vx)?;
-                    if (x & 0x1) == 0x1 {
-                        self.v.set_f()?;
-                    }
-                    x = x >> 1 ;
-                    self.v.write(*vx,x)?;
+                Instruction::SHR(vx, vy) => {
+                    let x = if self.quirks.shift_uses_vy { self.v.read(*vy)? } else { self.v.read(*vx)? };
+                    self.v.write(*vx, x >> 1)?;
+                    self.v.set_f(x & 0x1 == 0x1)?;
                 }
                 Instruction::SUBN(vx,vy) => {
-                    self.v.clr_f()?;
-                    let mut x = self.v.read(*vx)?;
-                    let mut y = self.v.read(*vy)?;
-                    if y <= x{
-                    }
-                    else {
-                        self.v.set_f()?;
-                    }
-                    let result = y as u16 - x as u16;
-                    self.v.write(*vx, (result & 0xFF) as CellValue)?;
+                    let x = self.v.read(*vx)?;
+                    let y = self.v.read(*vy)?;
+                    let (result, borrow) = y.overflowing_sub(x);
+                    self.v.write(*vx, result)?;
+                    self.v.set_f(!borrow)?;
                 }
-                Instruction::SHL(vx) => {
-                    let mut x = self.v.read(*vx)?;
-                    if ((x & 0b1000_0000) >> 7) == 0x1 {
-                        self.v.set_f()?;
-                    }
-                    x = x << 1 ;
-                    self.v.write(*vx,x)?;
+                Instruction::SHL(vx, vy) => {
+                    let x = if self.quirks.shift_uses_vy { self.v.read(*vy)? } else { self.v.read(*vx)? };
+                    self.v.write(*vx, x << 1)?;
+                    self.v.set_f(x & 0x80 == 0x80)?;
                 }
                 Instruction::LD(vx,vy) => {
                     let y = self.v.read(*vy)?;
@@ -880,46 +1279,74 @@ impl CPU {
                     self.index_register.set(*addr)?;
                 }
                 Instruction::JP_V0(addr) => {
-                    let v0 = self.v.read(0)?;
-                    self.pc.change(*addr + v0 as Addr)?;
+                    let offset_register = if self.quirks.jump_with_vx { (*addr >> 8) & 0xF } else { 0 };
+                    let offset = self.v.read(offset_register)?;
+                    self.pc.change(*addr + offset as Addr)?;
                     increase_pc = false;
                 }
                 Instruction::RNDi(vx, kk) => {
-                    let mut rng = rand::thread_rng().gen_range(0..=255);
-                    rng = rng & *kk ;
+                    let rng = self.random.next_byte() & *kk;
                     self.v.write(*vx, rng)?;
                 }
+                Instruction::DRW(_, _, _) if self.quirks.display_wait && !self.vblank => {
+                    // Fetched again after the next refresh
+                    self.display_wait = true;
+                    increase_pc = false;
+                }
                 Instruction::DRW(vx,vy, n) => {
+                    self.vblank = false;
                     let index = self.index_register.get()?;
-                    let mut set_f = 0;
-                    for i in 0..*n{
-                        let value = self.ram.read(index + i as usize)?;
-                        let y = (self.v.read(*vy)? + i)  as usize% FRAME_BUFFER_HEIGHT ;
-                        for j in 0..8 {
-                            let x = (self.v.read(*vx)? + j) as usize % FRAME_BUFFER_LENGTH ;
-                            let pixel = value >> (7-j) & 1;
-                            let old_value = self.frame_buff.read(y,x)?;
-                            set_f |= pixel^old_value;
-                            self.frame_buff.write(y,x,pixel^old_value)?;
-                        }
-                        if set_f != 0{
-                            self.v.set_f()?;
+                    let (width, height) = (self.frame_buff.width(), self.frame_buff.height());
+                    // The origin always wraps, the rest of the sprite may be clipped
+                    let origin_x = self.v.read(*vx)? as usize % width;
+                    let origin_y = self.v.read(*vy)? as usize % height;
+                    // Dxy0 draws a 16x16 sprite, two bytes per row
+                    let (rows, row_bytes) = if *n == 0 { (16, 2) } else { (*n as usize, 1) };
+                    let sprite_size = rows * row_bytes;
+                    let mut collision = false;
+                    // Each selected plane gets its own sprite, stored one after the other
+                    let planes = (0..2).map(|p| 1u8 << p).filter(|plane| self.planes & plane != 0);
+                    for (drawn, plane) in planes.enumerate() {
+                        let sprite = index + drawn * sprite_size;
+                        for i in 0..rows{
+                            let mut value = 0u16;
+                            for byte in 0..row_bytes {
+                                value = (value << 8) | self.ram.read(sprite + i * row_bytes + byte)? as u16;
+                            }
+                            let row_width = 8 * row_bytes;
+                            let y = origin_y + i;
+                            if y >= height && self.quirks.clip_sprites {
+                                break;
+                            }
+                            let y = y % height ;
+                            for j in 0..row_width {
+                                let x = origin_x + j;
+                                if x >= width && self.quirks.clip_sprites {
+                                    break;
+                                }
+                                let x = x % width ;
+                                if value >> (row_width - 1 - j) & 1 == 0 {
+                                    continue;
+                                }
+                                let old_value = self.frame_buff.read(y,x)?;
+                                collision |= old_value & plane != 0;
+                                self.frame_buff.write(y,x,old_value ^ plane)?;
+                            }
                         }
                     }
+                    self.v.set_f(collision)?;
                     self.set_refresh()?;
                 }
                 Instruction::SKP(vx) => {
-                    let x = self.v.read(*vx)?;
-                    let skip = self.keyboard & (1<<x) ;
-                    if skip != 0 {
-                        self.pc.incr()?;
+                    let x = self.v.read(*vx)? & 0xF;
+                    if self.is_key_pressed(x) {
+                        self.skip_next()?;
                     }
                 }
                 Instruction::SKNP(vx) => {
-                    let x = self.v.read(*vx)?;
-                    let skip = self.keyboard & (1<<x) ;
-                    if skip == 0 {
-                        self.pc.incr()?;
+                    let x = self.v.read(*vx)? & 0xF;
+                    if !self.is_key_pressed(x) {
+                        self.skip_next()?;
                     }
                 }
                 Instruction::LD_DT(vx) => {
@@ -927,9 +1354,10 @@ impl CPU {
                     self.v.write(*vx, dt_value)?;
                 }
                 Instruction::LD_K(vx) => {
-                    //TODO: Awful code, not working, consider changing it asap
-                    let key = (self.keyboard & 0x1) as CellValue;
-                    self.v.write(*vx, key)?;
+                    // PC moves on when wait_key() sees the key released
+                    self.key_down = None;
+                    self.state = CpuState::WAIT_KEY(*vx);
+                    increase_pc = false;
                 }
                 Instruction::SET_DT(vx) => {
                     let x = self.v.read(*vx)?;
@@ -946,9 +1374,12 @@ impl CPU {
                     self.index_register.set(i)?;
                 }
                 Instruction::LD_F(vx) => {
-                    let i = *vx;
-                    self.index_register.set(i)?;
-                    println!("MAYBE ME?");
+                    let digit = (self.v.read(*vx)? & 0xF) as Addr;
+                    self.index_register.set(self.font_address + digit * FONT_GLYPH_SIZE)?;
+                }
+                Instruction::LD_HF(vx) => {
+                    let digit = (self.v.read(*vx)? & 0xF) as Addr;
+                    self.index_register.set(self.big_font_address() + digit * BIG_FONT_GLYPH_SIZE)?;
                 }
                 Instruction::LD_B(vx) => {
                     let mut i = self.index_register.get()?;
@@ -962,7 +1393,6 @@ impl CPU {
                     self.ram.write(i, c)?;
                     i += 1;
                     self.ram.write(i, d)?;   
-                    cycles = 3u64;                 
                 }
                 Instruction::ST_UNTIL(vx) => {
                     let i_value = self.index_register.get()?;
@@ -970,7 +1400,10 @@ impl CPU {
                         let x = self.v.read(i as VIndex)?;
                         self.ram.write(i_value + i, x)?;
                     }
-                    cycles = *vx as u64;
+                    if self.quirks.load_store_increments_i {
+                        let last = if self.quirks.load_store_increments_i_by_x { 0 } else { 1 };
+                        self.index_register.set(i_value + *vx + last)?;
+                    }
                 }
                 Instruction::LD_UNTIL(vx) => {
                     let i_value = self.index_register.get()?;
@@ -978,7 +1411,49 @@ impl CPU {
                         let value = self.ram.read(i_value + i)?;
                         self.v.write(i, value)?;
                     }
-                    cycles = *vx as u64 ;
+                    if self.quirks.load_store_increments_i {
+                        let last = if self.quirks.load_store_increments_i_by_x { 0 } else { 1 };
+                        self.index_register.set(i_value + *vx + last)?;
+                    }
+                }
+                Instruction::LD_I_LONG(addr) => {
+                    self.index_register.set(*addr)?;
+                    // Skip the address word
+                    self.pc.incr()?;
+                }
+                Instruction::PLANE(n) => {
+                    self.planes = *n & PLANES_MASK;
+                }
+                Instruction::AUDIO => {
+                    let i_value = self.index_register.get()?;
+                    for offset in 0..AUDIO_PATTERN_SIZE {
+                        self.audio_pattern[offset] = self.ram.read(i_value + offset)?;
+                    }
+                }
+                Instruction::PITCH(vx) => {
+                    self.pitch = self.v.read(*vx)?;
+                }
+                Instruction::ST_RANGE(vx, vy) => {
+                    let i_value = self.index_register.get()?;
+                    for (offset, reg) in register_range(*vx, *vy).enumerate() {
+                        self.ram.write(i_value + offset, self.v.read(reg)?)?;
+                    }
+                }
+                Instruction::LD_RANGE(vx, vy) => {
+                    let i_value = self.index_register.get()?;
+                    for (offset, reg) in register_range(*vx, *vy).enumerate() {
+                        self.v.write(reg, self.ram.read(i_value + offset)?)?;
+                    }
+                }
+                Instruction::ST_R(vx) => {
+                    for i in 0..=*vx {
+                        self.rpl[i] = self.v.read(i)?;
+                    }
+                }
+                Instruction::LD_R(vx) => {
+                    for i in 0..=*vx {
+                        self.v.write(i, self.rpl[i])?;
+                    }
                 }
             }
         }
@@ -987,18 +1462,48 @@ impl CPU {
             self.pc.incr()?;
         }
 
-        Ok(cycles)
+        Ok(())
+
+    }
+
+}
+
+/// Direct access for tests to set up a machine without running instructions
+#[cfg(test)]
+impl CPU {
+
+    pub(crate) fn set_register(&mut self, index: VIndex, value: VValue) -> Result<(), Chip8Error> {
+        self.v.write(index, value)
+    }
+
+    pub(crate) fn set_index_register(&mut self, addr: Addr) {
+        self.index_register.value = addr;
+    }
 
+    pub(crate) fn write_memory(&mut self, addr: Addr, value: CellValue) -> Result<(), Chip8Error> {
+        self.ram.write(addr, value)
     }
 
+    /// Memory pages the snapshot still shares with the CPU
+    pub(crate) fn shared_pages(&self, snapshot: &Snapshot) -> usize {
+        self.ram.pages.iter().zip(snapshot.cpu.ram.pages.iter())
+            .filter(|(page, other)| Arc::ptr_eq(page, other))
+            .count()
+    }
 }
 
 /// Instruction Set
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     SYS(Addr),
     CLS,
     RET,
+    SCD(u8),
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
     JP(Addr),
     CALL(Addr),
     SEi(VIndex, VValue),
@@ -1012,9 +1517,9 @@ pub enum Instruction {
     XOR(VIndex, VIndex),
     ADD(VIndex, VIndex),
     SUB(VIndex, VIndex),
-    SHR(VIndex),
+    SHR(VIndex, VIndex),
     SUBN(VIndex, VIndex),
-    SHL(VIndex),
+    SHL(VIndex, VIndex),
     SNE(VIndex, VIndex),
     LD_I(Addr),
     JP_V0(Addr),
@@ -1030,5 +1535,421 @@ pub enum Instruction {
     LD_F(VIndex),
     LD_B(VIndex),
     ST_UNTIL(VIndex),
-    LD_UNTIL(VIndex)
+    LD_UNTIL(VIndex),
+    LD_HF(VIndex),
+    ST_R(VIndex),
+    LD_R(VIndex),
+    LD_I_LONG(Addr),
+    PLANE(u8),
+    AUDIO,
+    PITCH(VIndex),
+    ST_RANGE(VIndex, VIndex),
+    LD_RANGE(VIndex, VIndex)
+}
+
+impl Instruction {
+
+    /// Decodes a single opcode. F000 NNNN decodes to LD_I_LONG(0), its address
+    /// being the next word in memory, see CPU::instruction_at
+    pub fn decode(opcode: u16) -> Result<Instruction, Chip8Error> {
+
+        let bytes = (
+            ((opcode & 0xF000) >> 12)as u8,
+            ((opcode & 0x0F00) >> 8)as u8,
+            ((opcode & 0x00F0) >> 4)as u8,
+            (opcode & 0x000F) as u8
+        );
+
+        let instr = match bytes {
+
+            (0, 0, 0xC, n) => {
+                Instruction::SCD(n)
+            },
+
+            (0, 0, 0xE, 0) => {
+                Instruction::CLS
+            },
+
+            (0,0,0xE, 0xE) => {
+                Instruction::RET
+            },
+
+            (0, 0, 0xF, 0xB) => {
+                Instruction::SCR
+            },
+
+            (0, 0, 0xF, 0xC) => {
+                Instruction::SCL
+            },
+
+            (0, 0, 0xF, 0xD) => {
+                Instruction::EXIT
+            },
+
+            (0, 0, 0xF, 0xE) => {
+                Instruction::LOW
+            },
+
+            (0, 0, 0xF, 0xF) => {
+                Instruction::HIGH
+            },
+
+            // Machine code routine, every 00xx opcode above being taken
+            (0,_,_,_) => {
+                let addr = (opcode & 0x0FFF) as Addr;
+                Instruction::SYS(addr)
+            },
+
+            (1,_,_,_) => {
+                let addr = (opcode & 0x0FFF) as Addr;
+                Instruction::JP(addr)
+            },
+
+            (2,_,_,_) => {
+                let addr = (opcode & 0x0FFF) as Addr;
+                Instruction::CALL(addr)
+            },
+
+            (3,x,_,_) => {
+                let k = (opcode & 0x00FF) as VValue;  
+                Instruction::SEi(x as VIndex, k)
+            },
+
+            (4,x,_,_) => {
+                let kk = (opcode & 0x00FF) as VValue;  
+                Instruction::SNEi(x as VIndex, kk)
+            },
+
+            (5,x,y,0) => {
+                Instruction::SE(x as VIndex,y as VIndex)
+            },
+
+            (5,x,y,2) => {
+                Instruction::ST_RANGE(x as VIndex,y as VIndex)
+            },
+
+            (5,x,y,3) => {
+                Instruction::LD_RANGE(x as VIndex,y as VIndex)
+            },
+
+            (6,x,_,_) => {
+                let kk = (opcode & 0x00FF) as VValue; 
+                Instruction::LDi(x as VIndex, kk)
+            },
+
+            (7,x,_,_) => {
+                let kk = (opcode & 0x00FF) as VValue; 
+                Instruction::ADDi(x as VIndex, kk)
+            },
+
+            (8,x,y,0) => {
+                Instruction::LD(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,1) => {
+                Instruction::OR(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,2) => {
+                Instruction::AND(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,3) => {
+                Instruction::XOR(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,4) => {
+                Instruction::ADD(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,5) => {
+                Instruction::SUB(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,6) => {
+                Instruction::SHR(x as VIndex, y as VIndex)
+            },
+
+            (8,x,y,7) => {
+                Instruction::SUBN(x as VIndex,y as VIndex)
+            },
+
+            (8,x,y,0xE) => {
+                Instruction::SHL(x as VIndex, y as VIndex)
+            },
+
+            (9,x,y,0) => {
+                Instruction::SNE(x as VIndex,y as VIndex)
+            },
+
+            (0xA,_,_,_) => {
+                let addr = (opcode & 0x0FFF) as Addr;
+                Instruction::LD_I(addr)
+            },
+
+            (0xB,_,_,_) => {
+                let addr = (opcode & 0x0FFF) as Addr;
+                Instruction::JP_V0(addr)
+            },
+
+            (0xC,x,_,_) => {
+                let kk = (opcode & 0x00FF) as VValue; 
+                Instruction::RNDi(x as VIndex, kk)
+            },
+
+            (0xD,x,y,n) => {
+                Instruction::DRW(x as VIndex, y as VIndex, n)
+            },
+
+            (0xE, x, 9, 0xE) => {
+                Instruction::SKP(x as VIndex)
+            },
+
+            (0xE, x, 0xA, 1) => {
+                Instruction::SKNP(x as VIndex)
+            },
+
+            (0xF, 0, 0, 0) => {
+                Instruction::LD_I_LONG(0)
+            },
+
+            (0xF, n, 0, 1) => {
+                Instruction::PLANE(n)
+            },
+
+            (0xF, 0, 0, 2) => {
+                Instruction::AUDIO
+            },
+
+            (0xF, x, 0, 7) => {
+                Instruction::LD_DT(x as VIndex)
+            },
+
+            (0xF, x, 0, 0xA) => {
+                Instruction::LD_K(x as VIndex)
+            },
+
+            (0xF, x, 1, 5) => {
+                Instruction::SET_DT(x as VIndex)
+            },
+
+            (0xF, x, 1, 8) => {
+                Instruction::SET_ST(x as VIndex)
+            },
+
+            (0xF, x, 1, 0xE) => {
+                Instruction::ADD_I(x as VIndex)
+            },
+
+            (0xF, x, 2, 9) => {
+                Instruction::LD_F(x as VIndex)
+            },
+
+            (0xF, x, 3, 0xA) => {
+                Instruction::PITCH(x as VIndex)
+            },
+
+            (0xF, x, 3, 0) => {
+                Instruction::LD_HF(x as VIndex)
+            },
+
+            (0xF, x, 3, 3) => {
+                Instruction::LD_B(x as VIndex)
+            },
+
+            (0xF, x, 5, 5) => {
+                Instruction::ST_UNTIL(x as VIndex)
+            },
+
+            (0xF, x, 6, 5) => {
+                Instruction::LD_UNTIL(x as VIndex)
+            },
+
+            (0xF, x, 7, 5) => {
+                Instruction::ST_R(x as VIndex)
+            },
+
+            (0xF, x, 8, 5) => {
+                Instruction::LD_R(x as VIndex)
+            },
+
+            _ => {
+                return Err(Chip8Error::UnknownOpcode { opcode });
+            }
+        };
+
+        Ok(instr)
+    }
+
+    /// Inverse of decode. LD_I_LONG encodes to F000, its address being the word that
+    /// follows it in memory. Fields too large for their nibbles are truncated
+    pub fn encode(&self) -> u16 {
+        let x = |vx: VIndex| ((vx & 0xF) as u16) << 8;
+        let xy = |vx: VIndex, vy: VIndex| x(vx) | ((vy & 0xF) as u16) << 4;
+        let nnn = |addr: Addr| (addr & 0xFFF) as u16;
+        match *self {
+            Instruction::SYS(addr) => nnn(addr),
+            Instruction::CLS => 0x00E0,
+            Instruction::RET => 0x00EE,
+            Instruction::SCD(n) => 0x00C0 | (n & 0xF) as u16,
+            Instruction::SCR => 0x00FB,
+            Instruction::SCL => 0x00FC,
+            Instruction::EXIT => 0x00FD,
+            Instruction::LOW => 0x00FE,
+            Instruction::HIGH => 0x00FF,
+            Instruction::JP(addr) => 0x1000 | nnn(addr),
+            Instruction::CALL(addr) => 0x2000 | nnn(addr),
+            Instruction::SEi(vx, kk) => 0x3000 | x(vx) | kk as u16,
+            Instruction::SNEi(vx, kk) => 0x4000 | x(vx) | kk as u16,
+            Instruction::SE(vx, vy) => 0x5000 | xy(vx, vy),
+            Instruction::ST_RANGE(vx, vy) => 0x5002 | xy(vx, vy),
+            Instruction::LD_RANGE(vx, vy) => 0x5003 | xy(vx, vy),
+            Instruction::LDi(vx, kk) => 0x6000 | x(vx) | kk as u16,
+            Instruction::ADDi(vx, kk) => 0x7000 | x(vx) | kk as u16,
+            Instruction::LD(vx, vy) => 0x8000 | xy(vx, vy),
+            Instruction::OR(vx, vy) => 0x8001 | xy(vx, vy),
+            Instruction::AND(vx, vy) => 0x8002 | xy(vx, vy),
+            Instruction::XOR(vx, vy) => 0x8003 | xy(vx, vy),
+            Instruction::ADD(vx, vy) => 0x8004 | xy(vx, vy),
+            Instruction::SUB(vx, vy) => 0x8005 | xy(vx, vy),
+            Instruction::SHR(vx, vy) => 0x8006 | xy(vx, vy),
+            Instruction::SUBN(vx, vy) => 0x8007 | xy(vx, vy),
+            Instruction::SHL(vx, vy) => 0x800E | xy(vx, vy),
+            Instruction::SNE(vx, vy) => 0x9000 | xy(vx, vy),
+            Instruction::LD_I(addr) => 0xA000 | nnn(addr),
+            Instruction::JP_V0(addr) => 0xB000 | nnn(addr),
+            Instruction::RNDi(vx, kk) => 0xC000 | x(vx) | kk as u16,
+            Instruction::DRW(vx, vy, n) => 0xD000 | xy(vx, vy) | (n & 0xF) as u16,
+            Instruction::SKP(vx) => 0xE09E | x(vx),
+            Instruction::SKNP(vx) => 0xE0A1 | x(vx),
+            Instruction::LD_I_LONG(_) => 0xF000,
+            Instruction::PLANE(n) => 0xF001 | ((n & 0xF) as u16) << 8,
+            Instruction::AUDIO => 0xF002,
+            Instruction::LD_DT(vx) => 0xF007 | x(vx),
+            Instruction::LD_K(vx) => 0xF00A | x(vx),
+            Instruction::SET_DT(vx) => 0xF015 | x(vx),
+            Instruction::SET_ST(vx) => 0xF018 | x(vx),
+            Instruction::ADD_I(vx) => 0xF01E | x(vx),
+            Instruction::LD_F(vx) => 0xF029 | x(vx),
+            Instruction::LD_HF(vx) => 0xF030 | x(vx),
+            Instruction::LD_B(vx) => 0xF033 | x(vx),
+            Instruction::PITCH(vx) => 0xF03A | x(vx),
+            Instruction::ST_UNTIL(vx) => 0xF055 | x(vx),
+            Instruction::LD_UNTIL(vx) => 0xF065 | x(vx),
+            Instruction::ST_R(vx) => 0xF075 | x(vx),
+            Instruction::LD_R(vx) => 0xF085 | x(vx),
+        }
+    }
+
+    /// Big endian bytes of the instruction as stored in memory, 4 for LD_I_LONG
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LD_I_LONG(addr) = *self {
+            bytes.extend_from_slice(&(addr as u16).to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Bytes taken in memory, 4 for F000 NNNN and 2 for everything else
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LD_I_LONG(_) => 4,
+            _ => 2
+        }
+    }
+}
+
+/// Standard mnemonics, `LD V1, 0x20` or `DRW V0, V1, 5`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::SYS(addr) => write!(f, "SYS {:#05X}", addr),
+            Instruction::CLS => write!(f, "CLS"),
+            Instruction::RET => write!(f, "RET"),
+            Instruction::SCD(n) => write!(f, "SCD {}", n),
+            Instruction::SCR => write!(f, "SCR"),
+            Instruction::SCL => write!(f, "SCL"),
+            Instruction::EXIT => write!(f, "EXIT"),
+            Instruction::LOW => write!(f, "LOW"),
+            Instruction::HIGH => write!(f, "HIGH"),
+            Instruction::JP(addr) => write!(f, "JP {:#05X}", addr),
+            Instruction::CALL(addr) => write!(f, "CALL {:#05X}", addr),
+            Instruction::SEi(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SNEi(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::LDi(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::ADDi(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::SE(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LD(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::ADD(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SUB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SNE(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LD_I(addr) => write!(f, "LD I, {:#05X}", addr),
+            Instruction::JP_V0(addr) => write!(f, "JP V0, {:#05X}", addr),
+            Instruction::RNDi(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SKP(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SKNP(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LD_DT(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LD_K(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SET_DT(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SET_ST(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::ADD_I(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LD_F(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LD_B(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::ST_UNTIL(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LD_UNTIL(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LD_HF(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::ST_R(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LD_R(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::LD_I_LONG(addr) => write!(f, "LD I, LONG {:#06X}", addr),
+            Instruction::PLANE(n) => write!(f, "PLANE {}", n),
+            Instruction::AUDIO => write!(f, "AUDIO"),
+            Instruction::PITCH(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::ST_RANGE(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LD_RANGE(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+        }
+    }
+}
+
+/// Registers from vx to vy, walking backwards when vx > vy as 5xy2 and 5xy3 do
+fn register_range(vx: VIndex, vy: VIndex) -> Box<dyn Iterator<Item = VIndex>> {
+    if vx <= vy {
+        Box::new(vx..=vy)
+    }
+    else {
+        Box::new((vy..=vx).rev())
+    }
+}
+
+/// key_down value of a save state when no key is held during Fx0A
+const NO_KEY: u8 = 0xFF;
+
+pub(crate) fn read_u8(reader: &mut impl Read) -> Result<u8, Chip8Error> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub(crate) fn read_u16(reader: &mut impl Read) -> Result<u16, Chip8Error> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> Result<u32, Chip8Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> Result<u64, Chip8Error> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
 }
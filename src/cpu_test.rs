@@ -1,4 +1,1887 @@
 #[cfg(test)]
 mod tests {
+    use crate::cpu::*;
+    use crate::asm;
+    use crate::debugger::{Debugger, Breakpoint, StopReason};
+    use crate::disasm::Disassembly;
+    use crate::error::Chip8Error;
+    use crate::headless::{Headless, KeyPress, Outcome, Until};
+    use crate::history::History;
+    use crate::keymap::KeyMap;
+    use crate::movie::Movie;
+    use crate::quirks::Quirks;
+    use crate::random::SplitMix64;
+    use crate::screenshot::{self, Format};
+    use rand::RngCore;
+    use rand::rngs::mock::StepRng;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
 
-}
\ No newline at end of file
+    /// CHIP-8 behaviour, without waiting for refreshes so instructions count is predictable
+    fn test_quirks() -> Quirks {
+        Quirks { display_wait: false, ..Quirks::chip8() }
+    }
+
+    fn cpu_with_program(opcodes: &[u16]) -> CPU {
+        cpu_with_quirks(opcodes, test_quirks())
+    }
+
+    /// Loads opcodes at PROGRAM_START and powers the CPU on
+    fn cpu_with_quirks(opcodes: &[u16], quirks: Quirks) -> CPU {
+        let rom: Vec<u8> = opcodes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut cpu = CPU::new(Some(6000), quirks);
+        cpu.load_rom(&rom).unwrap();
+        cpu.power_on();
+        cpu
+    }
+
+    fn cpu_with_instructions(program: &[Instruction]) -> CPU {
+        let rom: Vec<u8> = program.iter().flat_map(Instruction::to_bytes).collect();
+        let mut cpu = CPU::new(Some(6000), test_quirks());
+        cpu.load_rom(&rom).unwrap();
+        cpu.power_on();
+        cpu
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn press_and_release_key() {
+        let mut cpu = CPU::new(None, Quirks::default());
+        cpu.press_key(0xA).unwrap();
+        assert!(cpu.is_key_pressed(0xA));
+        assert!(!cpu.is_key_pressed(0xB));
+        cpu.release_key(0xA).unwrap();
+        assert!(!cpu.is_key_pressed(0xA));
+        assert!(cpu.press_key(0x10).is_err());
+    }
+
+    #[test]
+    fn set_keys_replaces_keypad() {
+        let mut cpu = CPU::new(None, Quirks::default());
+        cpu.press_key(0x1).unwrap();
+        let mut keys = [false; KEY_COUNT];
+        keys[0xF] = true;
+        cpu.set_keys(&keys);
+        assert!(!cpu.is_key_pressed(0x1));
+        assert!(cpu.is_key_pressed(0xF));
+    }
+
+    #[test]
+    fn ld_k_waits_for_press_and_release() {
+        // LD V1, K ; LD V2, 0x42
+        let mut cpu = cpu_with_program(&[0xF10A, 0x6242]);
+        run(&mut cpu, 1);
+        assert!(cpu.is_waiting_for_key());
+
+        cpu.press_key(0x5).unwrap();
+        run(&mut cpu, 3);
+        assert!(cpu.is_waiting_for_key());
+
+        cpu.release_key(0x5).unwrap();
+        run(&mut cpu, 1);
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.register(1), 0x5);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(2), 0x42);
+    }
+
+    #[test]
+    fn ld_reads_and_writes_vf() {
+        let mut cpu = cpu_with_program(&[0x6F12, 0x80F0, 0x6E34, 0x8FE0]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.register(0), 0x12);
+        assert_eq!(cpu.register(VF), 0x34);
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        let mut cpu = cpu_with_program(&[0x60FF, 0x6102, 0x8014]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0x01);
+        assert_eq!(cpu.register(VF), 1);
+
+        let mut cpu = cpu_with_program(&[0x6F01, 0x6001, 0x6102, 0x8014]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.register(0), 0x03);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn add_flag_overrides_vf_result() {
+        let mut cpu = cpu_with_program(&[0x6F01, 0x6102, 0x8F14]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        let mut cpu = cpu_with_program(&[0x6005, 0x6105, 0x8015]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0x00);
+        assert_eq!(cpu.register(VF), 1);
+
+        let mut cpu = cpu_with_program(&[0x6004, 0x6105, 0x8015]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0xFF);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        let mut cpu = cpu_with_program(&[0x6005, 0x6103, 0x8017]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0xFE);
+        assert_eq!(cpu.register(VF), 0);
+
+        let mut cpu = cpu_with_program(&[0x6003, 0x6105, 0x8017]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0x02);
+        assert_eq!(cpu.register(VF), 1);
+    }
+
+    #[test]
+    fn shr_sets_shifted_out_bit() {
+        let mut cpu = cpu_with_program(&[0x6005, 0x8006]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(0), 0x02);
+        assert_eq!(cpu.register(VF), 1);
+
+        let mut cpu = cpu_with_program(&[0x6F04, 0x8F06]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn shl_sets_shifted_out_bit() {
+        let mut cpu = cpu_with_program(&[0x6081, 0x800E]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(0), 0x02);
+        assert_eq!(cpu.register(VF), 1);
+
+        let mut cpu = cpu_with_program(&[0x6F40, 0x8F0E]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn drw_sets_collision() {
+        // Draw the "0" glyph twice at (0, 0)
+        let mut cpu = cpu_with_program(&[0xF029, 0x6000, 0x6100, 0xD015, 0xD015]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.register(VF), 0);
+        assert_eq!(cpu.frame_buffer().read(0, 0).unwrap(), 1);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(VF), 1);
+        assert_eq!(cpu.frame_buffer().read(0, 0).unwrap(), 0);
+    }
+
+    /// Draws glyph d at (8 * (d % 8), 6 * (d / 8)) through LD_F
+    fn glyph_program() -> Vec<u16> {
+        let mut program = Vec::new();
+        for digit in 0..16u8 {
+            program.push(Instruction::LDi(0, digit));
+            program.push(Instruction::LD_F(0));
+            program.push(Instruction::LDi(1, 8 * (digit % 8)));
+            program.push(Instruction::LDi(2, 6 * (digit / 8)));
+            program.push(Instruction::DRW(1, 2, 5));
+        }
+        program.iter().map(Instruction::encode).collect()
+    }
+
+    fn assert_glyphs_rendered(cpu: &CPU) {
+        for digit in 0..16 {
+            let (x0, y0) = (8 * (digit % 8), 6 * (digit / 8));
+            for row in 0..FONT_GLYPH_SIZE {
+                let line = FONT_SET[digit * FONT_GLYPH_SIZE + row];
+                for col in 0..8 {
+                    let expected = (line >> (7 - col)) & 1;
+                    let pixel = cpu.frame_buffer().read(y0 + row, x0 + col).unwrap();
+                    assert_eq!(pixel, expected, "glyph {:X} row {} col {}", digit, row, col);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ld_f_renders_all_glyphs() {
+        let program = glyph_program();
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, program.len());
+        assert_glyphs_rendered(&cpu);
+        assert_eq!(cpu.index_register(), DEFAULT_FONT_ADDRESS + 0xF * FONT_GLYPH_SIZE);
+    }
+
+    #[test]
+    fn ld_f_follows_font_address() {
+        let program = glyph_program();
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_font_address(0x100).unwrap();
+        cpu.power_on();
+        run(&mut cpu, program.len());
+        assert_glyphs_rendered(&cpu);
+        assert!(cpu.set_font_address(0x1C0).is_err());
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        let program = [0x6003, 0x6108, 0x8016];
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0x04);
+
+        let quirks = Quirks { shift_uses_vy: false, ..test_quirks() };
+        let mut cpu = cpu_with_quirks(&program, quirks);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(0), 0x01);
+    }
+
+    #[test]
+    fn quirk_load_store_increments_i() {
+        let program = [0xA300, 0xF255];
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x303);
+
+        let mut cpu = cpu_with_quirks(&program, Quirks::superchip());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x300);
+
+        let mut cpu = cpu_with_quirks(&program, Quirks::chip48());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x302);
+
+        let program = [0xA300, 0xF265];
+        let mut cpu = cpu_with_quirks(&program, Quirks::chip48());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x302);
+    }
+
+    #[test]
+    fn quirk_jump_with_vx() {
+        let program = [0x6010, 0x6220, 0xB210];
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.program_counter(), 0x220);
+
+        let mut cpu = cpu_with_quirks(&program, Quirks::chip48());
+        run(&mut cpu, 3);
+        assert_eq!(cpu.program_counter(), 0x230);
+    }
+
+    #[test]
+    fn quirk_vf_reset() {
+        let program = [0x6F05, 0x8011];
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(VF), 0);
+
+        let mut cpu = cpu_with_quirks(&program, Quirks::chip48());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(VF), 5);
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        // Draw the "0" glyph at (62, 30), its corner lands on (1, 1) when wrapping
+        let program = [0xF029, 0x603E, 0x611E, 0xD015];
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.frame_buffer().read(30, 62).unwrap(), 1);
+        assert_eq!(cpu.frame_buffer().read(1, 1).unwrap(), 0);
+
+        let mut cpu = cpu_with_quirks(&program, Quirks::xochip());
+        run(&mut cpu, 4);
+        assert_eq!(cpu.frame_buffer().read(30, 62).unwrap(), 1);
+        assert_eq!(cpu.frame_buffer().read(1, 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn quirk_display_wait() {
+        let program = [0xF029, 0xD015, 0x1204];
+        let mut cpu = cpu_with_quirks(&program, Quirks::chip8());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.program_counter(), 0x202);
+        // The frame ends on the stalled draw, which goes through on the next one
+        cpu.run_frame(100).unwrap();
+        assert_eq!(cpu.program_counter(), 0x202);
+        cpu.run_frame(100).unwrap();
+        assert_eq!(cpu.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn quirks_presets_from_name() {
+        for name in Quirks::preset_names() {
+            assert!(name.parse::<Quirks>().is_ok());
+        }
+        assert_eq!("SUPER-CHIP".parse::<Quirks>().unwrap(), Quirks::superchip());
+        assert!("chip9".parse::<Quirks>().is_err());
+    }
+
+    fn lit_pixels(cpu: &CPU) -> Vec<(usize, usize)> {
+        let fb = cpu.frame_buffer();
+        let mut lit = Vec::new();
+        for (y, row) in fb.rows().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                if *pixel != 0 {
+                    lit.push((y, x));
+                }
+            }
+        }
+        lit
+    }
+
+    #[test]
+    fn schip_resolution_switch() {
+        let mut cpu = cpu_with_program(&[0x00FF, 0x00FE]);
+        run(&mut cpu, 1);
+        assert!(cpu.frame_buffer().is_hires());
+        assert_eq!(cpu.frame_buffer().width(), HIRES_FRAME_BUFFER_LENGTH);
+        assert_eq!(cpu.frame_buffer().height(), HIRES_FRAME_BUFFER_HEIGHT);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.frame_buffer().width(), FRAME_BUFFER_LENGTH);
+        assert_eq!(cpu.frame_buffer().height(), FRAME_BUFFER_HEIGHT);
+    }
+
+    #[test]
+    fn schip_draws_16x16_sprite() {
+        // Rows are two bytes wide, the first one is made of the 0xFF 0xFF opening big glyph "8"
+        let mut cpu = cpu_with_program(&[0x00FF, 0x6008, 0xF030, 0x6170, 0x6230, 0xD120]);
+        run(&mut cpu, 6);
+        let fb = cpu.frame_buffer();
+        for x in 0x70..0x80 {
+            assert_eq!(fb.read(0x30, x).unwrap(), 1);
+        }
+        // Clipped at the right edge of the 128 pixels wide screen
+        assert_eq!(fb.read(0x30, 0).unwrap(), 0);
+        assert_eq!(cpu.register(VF), 0);
+    }
+
+    #[test]
+    fn schip_scrolls() {
+        // Lit pixel at (0, 8) from the top left pixel of glyph "0"
+        let setup = [0xF029, 0x6008, 0x6100, 0xD011];
+        let mut cpu = cpu_with_program(&[setup[0], setup[1], setup[2], setup[3], 0x00C3]);
+        run(&mut cpu, 5);
+        assert!(lit_pixels(&cpu).contains(&(3, 8)));
+        assert!(!lit_pixels(&cpu).contains(&(0, 8)));
+
+        let mut cpu = cpu_with_program(&[setup[0], setup[1], setup[2], setup[3], 0x00FB]);
+        run(&mut cpu, 5);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 12), (0, 13), (0, 14), (0, 15)]);
+
+        let mut cpu = cpu_with_program(&[setup[0], setup[1], setup[2], setup[3], 0x00FC]);
+        run(&mut cpu, 5);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 4), (0, 5), (0, 6), (0, 7)]);
+    }
+
+    #[test]
+    fn schip_big_font_address() {
+        let mut cpu = cpu_with_program(&[0x6009, 0xF030]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), cpu.big_font_address() + 9 * BIG_FONT_GLYPH_SIZE);
+    }
+
+    #[test]
+    fn schip_rpl_flags() {
+        let mut cpu = cpu_with_program(&[0x6011, 0x6122, 0x6233, 0xF275, 0x6000, 0x6100, 0xF185]);
+        run(&mut cpu, 7);
+        assert_eq!(&cpu.rpl_flags()[..3], &[0x11, 0x22, 0x33]);
+        assert_eq!(cpu.register(0), 0x11);
+        assert_eq!(cpu.register(1), 0x22);
+    }
+
+    #[test]
+    fn schip_exit_stops_cpu() {
+        let mut cpu = cpu_with_program(&[0x00FD]);
+        run(&mut cpu, 1);
+        assert!(!cpu.is_on());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.program_counter(), PROGRAM_START);
+    }
+
+    #[test]
+    fn xochip_memory_size() {
+        assert_eq!(CPU::new(None, Quirks::chip8()).memory_size(), MEMORY_SIZE);
+        assert_eq!(CPU::new(None, Quirks::xochip()).memory_size(), EXTENDED_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn xochip_long_index_load() {
+        // Write V0 to 0x1234, out of reach of the 12 bits Annn
+        let mut cpu = cpu_with_quirks(&[0xF000, 0x1234, 0x6042, 0xF055, 0xF065], Quirks::xochip());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x1234);
+        assert_eq!(cpu.program_counter(), 0x206);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.index_register(), 0x1235);
+    }
+
+    #[test]
+    fn xochip_skip_jumps_over_long_load() {
+        let mut cpu = cpu_with_quirks(&[0x3000, 0xF000, 0x1234, 0x6101], Quirks::xochip());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(1), 0x01);
+        assert_eq!(cpu.index_register(), 0);
+    }
+
+    #[test]
+    fn xochip_draws_on_selected_planes() {
+        // Plane 2 only, then both planes with the "0" and "1" glyphs one after the other
+        let program = [0xF029, 0xF201, 0xD011, 0xF301, 0xD011];
+        let mut cpu = cpu_with_quirks(&program, Quirks::xochip());
+        run(&mut cpu, 3);
+        assert_eq!(cpu.selected_planes(), 2);
+        assert_eq!(cpu.frame_buffer().read(0, 0).unwrap(), 2);
+
+        // Plane 1 gets 0xF0, plane 2 gets 0x90 and collides at (0, 0)
+        run(&mut cpu, 2);
+        assert_eq!(cpu.frame_buffer().read(0, 0).unwrap(), 1);
+        assert_eq!(cpu.frame_buffer().read(0, 1).unwrap(), 3);
+        assert_eq!(cpu.frame_buffer().read(0, 3).unwrap(), 1);
+        assert_eq!(cpu.register(VF), 1);
+    }
+
+    #[test]
+    fn xochip_clear_only_selected_planes() {
+        let program = [0xF029, 0xF301, 0xD011, 0xF101, 0x00E0];
+        let mut cpu = cpu_with_quirks(&program, Quirks::xochip());
+        run(&mut cpu, 5);
+        assert_eq!(cpu.frame_buffer().read(0, 0).unwrap(), 2);
+        assert_eq!(cpu.frame_buffer().read(0, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn xochip_register_range() {
+        let program = [0x6111, 0x6222, 0x6333, 0xA300, 0x5132, 0x5313, 0x5403];
+        let mut cpu = cpu_with_quirks(&program, Quirks::xochip());
+        run(&mut cpu, 5);
+        assert_eq!(cpu.index_register(), 0x300);
+        // Loading backwards from V3 puts 0x11 in V3 and 0x33 in V1
+        run(&mut cpu, 1);
+        assert_eq!((cpu.register(1), cpu.register(2), cpu.register(3)), (0x33, 0x22, 0x11));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(4), 0x11);
+    }
+
+    #[test]
+    fn xochip_audio_pattern_and_pitch() {
+        let mut cpu = cpu_with_quirks(&[0xF029, 0xF002, 0x6070, 0xF03A], Quirks::xochip());
+        assert_eq!(cpu.audio_pattern(), &DEFAULT_AUDIO_PATTERN);
+        assert_eq!(cpu.playback_rate(), 4000.0);
+        run(&mut cpu, 4);
+        assert_eq!(&cpu.audio_pattern()[..], &FONT_SET[..AUDIO_PATTERN_SIZE]);
+        assert_eq!(cpu.pitch(), 0x70);
+        assert_eq!(cpu.playback_rate(), 4000.0 * 2f32.powf(1.0));
+    }
+
+    fn run_until_error(cpu: &mut CPU, instructions: usize) -> Chip8Error {
+        for _ in 0..instructions {
+            if let Err(e) = cpu.step() {
+                return e;
+            }
+        }
+        panic!("no error after {} instructions", instructions);
+    }
+
+    #[test]
+    fn illegal_opcode_reports_opcode_and_pc() {
+        let mut cpu = cpu_with_program(&[0x6000, 0x5001]);
+        match run_until_error(&mut cpu, 2) {
+            Chip8Error::IllegalOpcode { opcode, pc } => {
+                assert_eq!(opcode, 0x5001);
+                assert_eq!(pc, 0x202);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn stack_errors() {
+        let mut cpu = cpu_with_program(&[0x00EE]);
+        assert!(matches!(run_until_error(&mut cpu, 1), Chip8Error::StackUnderflow));
+
+        // Calls itself forever
+        let mut cpu = cpu_with_program(&[0x2200]);
+        assert!(matches!(run_until_error(&mut cpu, STACK_SIZE + 1), Chip8Error::StackOverflow));
+    }
+
+    #[test]
+    fn nested_calls_return_after_their_call() {
+        // 0x200 CALL 0x206 ; 0x202 LD V0, 1 ; 0x204 JP 0x204
+        // 0x206 CALL 0x20C ; 0x208 LD V1, 2 ; 0x20A RET ; 0x20C RET
+        let mut cpu = cpu_with_program(&[0x2206, 0x6001, 0x1204, 0x220C, 0x6102, 0x00EE, 0x00EE]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.stack(), &[0x202, 0x208]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x208);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert!(cpu.stack().is_empty());
+        run(&mut cpu, 1);
+        assert_eq!((cpu.register(0), cpu.register(1)), (1, 2));
+    }
+
+    #[test]
+    fn out_of_range_memory_access() {
+        // I = 0xFFF, storing V0 and V1 writes past the end of the memory
+        let mut cpu = cpu_with_program(&[0xAFFF, 0xF155]);
+        match run_until_error(&mut cpu, 2) {
+            Chip8Error::MemoryOutOfRange { addr } => assert_eq!(addr, MEMORY_SIZE),
+            e => panic!("unexpected error {}", e),
+        }
+        assert!(cpu.press_key(0x10).is_err());
+    }
+
+    #[test]
+    fn load_rom_size_limit() {
+        let mut cpu = CPU::new(None, Quirks::chip8());
+        assert_eq!(cpu.max_rom_size(), 3584);
+        assert!(cpu.load_rom(&[0; 3584]).is_ok());
+        match cpu.load_rom(&[0; 3585]) {
+            Err(Chip8Error::RomTooLarge { size, max }) => assert_eq!((size, max), (3585, 3584)),
+            _ => panic!("oversized ROM accepted"),
+        }
+
+        let mut cpu = CPU::new(None, Quirks::xochip());
+        assert!(cpu.load_rom(&[0; 3585]).is_ok());
+    }
+
+    #[test]
+    fn load_reader_copies_at_program_start() {
+        let mut cpu = CPU::new(None, Quirks::default());
+        cpu.load_reader(&[0x60, 0x2A][..]).unwrap();
+        cpu.power_on();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(0), 0x2A);
+    }
+
+    #[test]
+    fn load_text_reports_malformed_line() {
+        let mut cpu = CPU::new(None, Quirks::default());
+        assert!(cpu.load_text("6001\n\n 7001 \n").is_ok());
+        match cpu.load_text("6001\n7001\n70G1\n") {
+            Err(Chip8Error::InvalidHexLine { line, content }) => {
+                assert_eq!(line, 3);
+                assert_eq!(content, "70G1");
+            }
+            _ => panic!("malformed line accepted"),
+        }
+        assert!(cpu.load_text("12345").is_err());
+    }
+
+    #[test]
+    fn load_files() {
+        let mut cpu = CPU::new(None, Quirks::default());
+        let missing = std::env::temp_dir().join("chip8_test_missing.ch8");
+        assert!(matches!(cpu.loadb(&missing), Err(Chip8Error::Io(_))));
+
+        let path = std::env::temp_dir().join("chip8_test_loadt.txt");
+        fs::write(&path, "602A\n").unwrap();
+        cpu.loadt(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        cpu.power_on();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(0), 0x2A);
+    }
+
+    #[test]
+    fn step_executes_one_instruction() {
+        let mut cpu = cpu_with_program(&[0x6001, 0x6102, 0x6203]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert_eq!((cpu.register(0), cpu.register(1)), (0x01, 0x00));
+    }
+
+    #[test]
+    fn run_frame_runs_cycles_then_ticks_timers() {
+        // DT = 10, then a 4 instructions loop
+        let mut cpu = cpu_with_program(&[0x600A, 0xF015, 0x7101, 0x7201, 0x7301, 0x1204]);
+        cpu.run_frame(5).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 9);
+        assert_eq!(cpu.register(1), 1);
+        assert_eq!(cpu.register(3), 1);
+        assert_eq!(cpu.program_counter(), 0x20A);
+        assert_eq!(CPU::new(Some(30), Quirks::default()).cycles_per_frame(), 1);
+    }
+
+    #[test]
+    fn delay_timer_reaches_zero_after_exact_frames() {
+        // DT = ST = 5, then LD_B and ST_UNTIL in a loop
+        let mut cpu = cpu_with_program(&[0x6005, 0xF015, 0xF018, 0xA300, 0xF033, 0xF555, 0x1208]);
+        run(&mut cpu, 3);
+        for frame in 1..=5 {
+            assert!(cpu.sound().unwrap());
+            cpu.run_frame(10).unwrap();
+            assert_eq!(cpu.delay_timer().get().unwrap(), 5 - frame);
+        }
+        assert!(!cpu.sound().unwrap());
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0);
+    }
+
+    #[test]
+    fn run_cycles_ticks_on_emulated_time() {
+        // 600Hz gives 10 instructions per tick
+        let mut cpu = CPU::new(Some(600), test_quirks());
+        cpu.load_rom(&[0x60, 0x03, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        cpu.power_on();
+        cpu.run_cycles(9).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 3);
+        // Cycles left over by the previous call still count
+        cpu.run_cycles(1).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 2);
+        cpu.run_cycles(20).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0);
+    }
+
+    #[test]
+    fn run_cycles_below_60hz() {
+        let mut cpu = CPU::new(Some(30), test_quirks());
+        cpu.load_rom(&[0x60, 0x03, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        cpu.power_on();
+        // Every instruction lasts two ticks
+        cpu.run_cycles(2).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 1);
+        cpu.run_cycles(1).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0);
+    }
+
+    #[test]
+    fn run_cycles_at_frequency_60_does_not_divide() {
+        // DT = 0xFF, then a loop on itself
+        let mut cpu = CPU::new(Some(700), test_quirks());
+        cpu.load_rom(&[0x60, 0xFF, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        cpu.power_on();
+        cpu.run_cycles(2).unwrap();
+        // 11 and 2/3 cycles per tick, one second after DT was set
+        cpu.run_cycles(700).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0xFF - 60);
+        let mut state = Vec::new();
+        cpu.save_state(&mut state).unwrap();
+        cpu.run_cycles(35).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0xFF - 63);
+
+        // The fraction of a tick left is saved with the machine
+        let mut restored = CPU::new(None, Quirks::default());
+        restored.load_state(&state[..]).unwrap();
+        restored.run_cycles(35).unwrap();
+        assert_eq!(restored.delay_timer().get().unwrap(), 0xFF - 63);
+    }
+
+    #[test]
+    fn host_ticks_timers() {
+        let mut cpu = cpu_with_program(&[0x6002, 0xF015]);
+        run(&mut cpu, 2);
+        cpu.tick_timers().unwrap();
+        cpu.tick_timers().unwrap();
+        cpu.tick_timers().unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 0);
+    }
+
+    fn state_bytes(cpu: &CPU) -> Vec<u8> {
+        let mut state = Vec::new();
+        cpu.save_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut program = vec![0x00FF, 0x2210, 0x6A07, 0xFA15, 0x1208, 0, 0, 0];
+        // Subroutine at 0x210 drawing every glyph, returned from afterwards
+        program.extend(glyph_program());
+        program.push(0x00EE);
+        let mut cpu = cpu_with_quirks(&program, Quirks { display_wait: false, ..Quirks::superchip() });
+        run(&mut cpu, 40);
+        cpu.press_key(0x3).unwrap();
+        let saved = state_bytes(&cpu);
+
+        let mut restored = CPU::new(None, Quirks::xochip());
+        restored.load_state(&saved[..]).unwrap();
+        assert_eq!(state_bytes(&restored), saved);
+        assert_eq!(*restored.quirks(), Quirks { display_wait: false, ..Quirks::superchip() });
+        assert!(restored.frame_buffer().is_hires());
+
+        // Both machines carry on identically
+        for _ in 0..3 {
+            cpu.run_frame(20).unwrap();
+            restored.run_frame(20).unwrap();
+        }
+        assert_eq!(state_bytes(&restored), state_bytes(&cpu));
+        assert_eq!(restored.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn save_state_keeps_pending_key_wait() {
+        let mut cpu = cpu_with_program(&[0xF50A, 0x1202]);
+        cpu.press_key(0x9).unwrap();
+        run(&mut cpu, 2);
+        let mut restored = CPU::new(None, test_quirks());
+        restored.load_state(&state_bytes(&cpu)[..]).unwrap();
+        assert!(restored.is_waiting_for_key());
+        restored.release_key(0x9).unwrap();
+        run(&mut restored, 1);
+        assert!(!restored.is_waiting_for_key());
+        assert_eq!(restored.register(5), 0x9);
+    }
+
+    #[test]
+    fn load_state_rejects_bad_input() {
+        let mut cpu = cpu_with_program(&[0x6042]);
+        let before = state_bytes(&cpu);
+        let mut state = before.clone();
+
+        state[0] = b'X';
+        assert!(matches!(cpu.load_state(&state[..]), Err(Chip8Error::InvalidSaveState(_))));
+        state[0] = SAVE_STATE_MAGIC[0];
+        state[4] = SAVE_STATE_VERSION + 1;
+        assert!(matches!(
+            cpu.load_state(&state[..]),
+            Err(Chip8Error::UnsupportedSaveStateVersion { version }) if version == SAVE_STATE_VERSION + 1
+        ));
+        assert!(matches!(cpu.load_state(&before[..before.len() - 1]), Err(Chip8Error::Io(_))));
+        assert_eq!(state_bytes(&cpu), before);
+    }
+
+    #[test]
+    fn quirks_bits_round_trip() {
+        for name in Quirks::preset_names().iter() {
+            let quirks: Quirks = name.parse().unwrap();
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks);
+        }
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut cpu = cpu_with_program(&[0x6001, 0x7001, 0x2208, 0x1202, 0x00E0, 0x00EE]);
+        run(&mut cpu, 3);
+        let snapshot = cpu.snapshot();
+        let saved = state_bytes(&cpu);
+        run(&mut cpu, 10);
+        cpu.restore(&snapshot);
+        assert_eq!(state_bytes(&cpu), saved);
+        assert_eq!(cpu.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn history_rewinds_frames() {
+        // V0 counts frames
+        let mut cpu = cpu_with_program(&[0x7001, 0x1200]);
+        let mut history = History::new(3);
+        for _ in 0..5 {
+            history.push(&cpu);
+            cpu.run_frame(2).unwrap();
+        }
+        assert_eq!(cpu.register(0), 5);
+        assert_eq!(history.len(), 3);
+
+        for expected in (2..5).rev() {
+            assert!(history.rewind(&mut cpu));
+            assert_eq!(cpu.register(0), expected);
+        }
+        // Older frames were dropped
+        assert!(!history.rewind(&mut cpu));
+        assert_eq!(cpu.register(0), 2);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn snapshots_share_unchanged_memory() {
+        // I = 0x8000, store V0 there, in a loop
+        let program = [0x6007, 0xF000, 0x8000, 0xF055, 0x1206];
+        let mut cpu = cpu_with_quirks(&program, Quirks::xochip());
+        run(&mut cpu, 2);
+        let snapshot = cpu.snapshot();
+        let pages = EXTENDED_MEMORY_SIZE / MEMORY_PAGE_SIZE;
+        assert_eq!(cpu.shared_pages(&snapshot), pages);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.shared_pages(&snapshot), pages - 1);
+        assert_eq!(cpu.read_memory(0x8000).unwrap(), 7);
+        cpu.restore(&snapshot);
+        assert_eq!(cpu.read_memory(0x8000).unwrap(), 0);
+    }
+
+    #[test]
+    fn empty_history_capacity() {
+        let cpu = cpu_with_program(&[0x1200]);
+        let mut history = History::new(0);
+        history.push(&cpu);
+        assert!(history.is_empty());
+    }
+
+    /// Main calls a subroutine storing V0 and V1 at 0x300, then loops at 0x206
+    fn debugged_program() -> (CPU, Debugger) {
+        let cpu = cpu_with_program(&[0x6001, 0x2208, 0x7001, 0x1206, 0x6105, 0xA300, 0xF155, 0x00EE]);
+        (cpu, Debugger::new())
+    }
+
+    #[test]
+    fn debugger_breaks_on_address() {
+        let (mut cpu, mut debugger) = debugged_program();
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.run_frame(&mut cpu, 10).unwrap(), None);
+        assert_eq!(cpu.program_counter(), 0x200);
+
+        debugger.command(&mut cpu, "b 204").unwrap();
+        debugger.command(&mut cpu, "c").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), Some(StopReason::Breakpoint { pc: 0x204, opcode: 0x7001 }));
+        assert!(debugger.is_paused());
+        assert_eq!(cpu.register(1), 5);
+
+        // Continuing leaves the breakpoint behind
+        debugger.command(&mut cpu, "continue").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), None);
+        assert_eq!(cpu.register(0), 2);
+    }
+
+    #[test]
+    fn debugger_steps_over_call() {
+        let (mut cpu, mut debugger) = debugged_program();
+        debugger.command(&mut cpu, "s").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), Some(StopReason::Stepped));
+        assert_eq!(cpu.program_counter(), 0x202);
+        debugger.command(&mut cpu, "n").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), Some(StopReason::Stepped));
+        assert_eq!(cpu.program_counter(), 0x204);
+        assert!(cpu.stack().is_empty());
+        // Empty line repeats the last command
+        debugger.command(&mut cpu, "").unwrap();
+        debugger.run_frame(&mut cpu, 100).unwrap();
+        assert_eq!(cpu.program_counter(), 0x206);
+        debugger.command(&mut cpu, "step 3").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 2).unwrap(), None);
+        assert_eq!(debugger.run_frame(&mut cpu, 2).unwrap(), Some(StopReason::Stepped));
+    }
+
+    #[test]
+    fn debugger_steps_over_draws_waiting_for_refresh() {
+        // LD V0, 0 ; DRW V0, V0, 1 ; DRW V0, V0, 1 ; JP 0x206
+        let mut cpu = cpu_with_quirks(&[0x6000, 0xD001, 0xD001, 0x1206], Quirks::chip8());
+        let mut debugger = Debugger::new();
+        let mut step = |cpu: &mut CPU| {
+            debugger.command(cpu, "s").unwrap();
+            (1..=3).find(|_| debugger.run_frame(cpu, 10).unwrap() == Some(StopReason::Stepped))
+                .expect("step did not complete");
+            cpu.program_counter()
+        };
+        assert_eq!(step(&mut cpu), 0x202);
+        assert_eq!(step(&mut cpu), 0x204);
+        assert_eq!(step(&mut cpu), 0x206);
+        assert_eq!(step(&mut cpu), 0x206);
+        assert_eq!(cpu.cycles(), 4);
+    }
+
+    #[test]
+    fn debugger_stops_tick_timers() {
+        // LD V0, 5 ; LD DT, V0 ; JP 0x204
+        let mut cpu = cpu_with_program(&[0x6005, 0xF015, 0x1204]);
+        let mut debugger = Debugger::new();
+        debugger.command(&mut cpu, "b 204").unwrap();
+        debugger.command(&mut cpu, "c").unwrap();
+        assert!(matches!(debugger.run_frame(&mut cpu, 10).unwrap(), Some(StopReason::Breakpoint { .. })));
+        assert_eq!(cpu.delay_timer().get().unwrap(), 4);
+        // Paused frames don't tick
+        debugger.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.delay_timer().get().unwrap(), 4);
+    }
+
+    #[test]
+    fn debugger_breaks_on_opcode_pattern() {
+        let (mut cpu, mut debugger) = debugged_program();
+        assert_eq!(Breakpoint::opcode("8xy4").unwrap(), Breakpoint::Opcode { pattern: 0x8004, mask: 0xF00F });
+        assert!(Breakpoint::opcode("D12").is_err());
+        debugger.command(&mut cpu, "bo F?55").unwrap();
+        debugger.command(&mut cpu, "c").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), Some(StopReason::Breakpoint { pc: 0x20C, opcode: 0xF155 }));
+    }
+
+    #[test]
+    fn debugger_watchpoints() {
+        let (mut cpu, mut debugger) = debugged_program();
+        debugger.command(&mut cpu, "w v1").unwrap();
+        debugger.command(&mut cpu, "watch 300 301").unwrap();
+        debugger.command(&mut cpu, "c").unwrap();
+        assert_eq!(debugger.run_frame(&mut cpu, 100).unwrap(), Some(StopReason::RegisterChanged { index: 1, old: 0, new: 5 }));
+        debugger.command(&mut cpu, "c").unwrap();
+        assert_eq!(
+            debugger.run_frame(&mut cpu, 100).unwrap(),
+            Some(StopReason::MemoryChanged { addr: 0x300, old: 0, new: 1 })
+        );
+        assert_eq!(debugger.command(&mut cpu, "l").unwrap(), "0: watch V1\n1: watch 0x300..=0x301");
+        debugger.command(&mut cpu, "d 0").unwrap();
+        assert_eq!(debugger.watchpoints().len(), 1);
+    }
+
+    #[test]
+    fn debugger_inspection_commands() {
+        let (mut cpu, mut debugger) = debugged_program();
+        run(&mut cpu, 3);
+        let regs = debugger.command(&mut cpu, "regs").unwrap();
+        assert!(regs.contains("V0=01"));
+        assert!(regs.contains("V1=05"));
+        assert!(regs.contains("PC=0x20A"));
+        assert!(regs.contains("stack: [0x204]"));
+        assert_eq!(debugger.command(&mut cpu, "x 0x200 4").unwrap(), "0x0200: 60 01 22 08");
+        let tail = debugger.command(&mut cpu, &format!("x 0xFFC {}", usize::MAX)).unwrap();
+        assert_eq!(tail, "0x0FFC: 00 00 00 00");
+        let listing = debugger.command(&mut cpu, "dis").unwrap();
+        assert_eq!(listing.lines().count(), 9);
+        assert!(listing.lines().any(|line| line.starts_with("> 0x20A: A300")));
+        assert!(matches!(debugger.command(&mut cpu, "frobnicate"), Err(Chip8Error::InvalidCommand(_))));
+        assert!(matches!(debugger.command(&mut cpu, "x 10000"), Err(Chip8Error::MemoryOutOfRange { .. })));
+    }
+
+    #[test]
+    fn instruction_mnemonics() {
+        let expected = [
+            (0x00E0, "CLS"), (0x00C4, "SCD 4"), (0x1234, "JP 0x234"), (0x2ABC, "CALL 0xABC"),
+            (0x3A0F, "SE VA, 0x0F"), (0x6120, "LD V1, 0x20"), (0x7FFF, "ADD VF, 0xFF"),
+            (0x8124, "ADD V1, V2"), (0x812E, "SHL V1, V2"), (0xA300, "LD I, 0x300"),
+            (0xB400, "JP V0, 0x400"), (0xC3FF, "RND V3, 0xFF"), (0xD015, "DRW V0, V1, 5"),
+            (0xE59E, "SKP V5"), (0xF207, "LD V2, DT"), (0xF20A, "LD V2, K"), (0xF218, "LD ST, V2"),
+            (0xF033, "LD B, V0"), (0xF355, "LD [I], V3"), (0xF365, "LD V3, [I]"),
+            (0xF000, "LD I, LONG 0x0000"), (0xF201, "PLANE 2"), (0x5132, "LD [I], V1-V3"),
+            (0x0123, "SYS 0x123"), (0x0000, "SYS 0x000"),
+        ];
+        for (opcode, mnemonic) in expected.iter() {
+            assert_eq!(Instruction::decode(*opcode).unwrap().to_string(), *mnemonic);
+        }
+        assert!(matches!(Instruction::decode(0x5001), Err(Chip8Error::UnknownOpcode { opcode: 0x5001 })));
+        assert!(matches!(Instruction::decode(0xE000), Err(Chip8Error::UnknownOpcode { .. })));
+    }
+
+    #[test]
+    fn disassembly_separates_code_and_data() {
+        let rom = [
+            0x22, 0x06, // CALL 0x206
+            0x12, 0x02, // JP 0x202
+            0xFF, 0x81, // sprite
+            0xA2, 0x04, // LD I, 0x204
+            0x30, 0x00, // SE V0, 0
+            0xF0, 0x00, 0x12, 0x34, // LD I, LONG 0x1234
+            0x00, 0xEE, // RET
+            0x12, 0x34, // unreachable
+        ];
+        let disassembly = Disassembly::new(&rom);
+        assert!(disassembly.is_code(0x200));
+        assert!(!disassembly.is_code(0x204));
+        assert!(disassembly.is_code(0x20C));
+        assert!(disassembly.is_code(0x20E));
+        assert!(!disassembly.is_code(0x210));
+        assert_eq!(disassembly.labels().collect::<Vec<_>>(), vec![(0x202, "L202"), (0x206, "sub_206")]);
+
+        let listing = disassembly.listing();
+        let lines: Vec<&str> = listing.lines().map(|line| line.split(';').next().unwrap().trim_end()).collect();
+        assert_eq!(lines, vec![
+            "    CALL sub_206",
+            "L202:",
+            "    JP L202",
+            "    db 0xFF, 0x81",
+            "sub_206:",
+            "    LD I, 0x204",
+            "    SE V0, 0x00",
+            "    LD I, LONG 0x1234",
+            "    RET",
+            "    db 0x12, 0x34",
+        ]);
+        assert!(listing.contains("; 0x20A  F0001234"));
+    }
+
+    #[test]
+    fn assemble_program() {
+        let source = "
+            SPRITE_HEIGHT = 2
+            X = 0x10 + 4      ; constants may use expressions
+            start:
+                LD V0, X
+                ld v1, 0
+                LD I, sprite
+                DRW V0, V1, SPRITE_HEIGHT
+                CALL wait
+            loop: JP loop
+            wait:
+                LD V2, 0x3C
+                LD DT, V2
+                LD I, LONG sprite + 1
+                ADD V3, -1
+                RET
+            sprite:
+                db 0b11111111, 0x81
+                dw 0x1234
+        ";
+        let rom = asm::assemble(source).unwrap();
+        assert_eq!(rom, vec![
+            0x60, 0x14, 0x61, 0x00, 0xA2, 0x18, 0xD0, 0x12, 0x22, 0x0C, 0x12, 0x0A,
+            0x62, 0x3C, 0xF2, 0x15, 0xF0, 0x00, 0x02, 0x19, 0x73, 0xFF, 0x00, 0xEE,
+            0xFF, 0x81, 0x12, 0x34,
+        ]);
+
+        let mut cpu = CPU::new(None, test_quirks());
+        cpu.load_assembly(source).unwrap();
+        cpu.power_on();
+        run(&mut cpu, 4);
+        assert_eq!(lit_pixels(&cpu).len(), 10);
+    }
+
+    #[test]
+    fn disassembly_reassembles() {
+        let mut rom: Vec<u8> = vec![0x22, 0x08, 0x12, 0x02, 0xFF, 0x81, 0x00, 0x00];
+        for opcode in [0xA204, 0x5232, 0x5233, 0xF000, 0x0300, 0xF201, 0x8126, 0xF30A, 0xF485, 0xB20E, 0x00EE].iter() {
+            rom.extend_from_slice(&(*opcode as u16).to_be_bytes());
+        }
+        let listing = Disassembly::new(&rom).listing();
+        assert_eq!(asm::assemble(&listing).unwrap(), rom);
+    }
+
+    fn assembly_error(source: &str) -> (usize, usize, String) {
+        match asm::assemble(source) {
+            Err(Chip8Error::Assembly { line, column, message, .. }) => (line, column, message),
+            other => panic!("expected an assembly error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn assembly_errors_locate_the_problem() {
+        assert_eq!(assembly_error("CLS\n    LD V1, 0x123"), (2, 12, "291 does not fit in 0xFF".to_string()));
+        assert_eq!(assembly_error("  FOO V1").1, 3);
+        assert_eq!(assembly_error("  FOO V1").2, "unknown mnemonic FOO");
+        assert_eq!(assembly_error("JP V0").2, "invalid operands for JP");
+        assert_eq!(assembly_error("JP nowhere + 2").1, 4);
+        assert_eq!(assembly_error("JP nowhere + 2").2, "undefined symbol nowhere");
+        assert_eq!(assembly_error("a: CLS\na: CLS"), (2, 1, "a is already defined".to_string()));
+        assert_eq!(assembly_error("A = C\nC = A\nJP A").2, "A is defined in terms of itself");
+        assert_eq!(assembly_error("db 1,, 2").1, 6);
+        assert_eq!(assembly_error("K = 5\nLD V0, K"), (1, 1, "\"K\" can't be used as a name".to_string()));
+        assert_eq!(assembly_error("b = 0x10\nLD V1, b").2, "\"b\" can't be used as a name");
+        for name in ["I", "dt", "St", "f", "HF", "r", "va"] {
+            assert!(assembly_error(&format!("{}: CLS", name)).2.contains("can't be used as a name"), "{}", name);
+        }
+        assert_eq!(assembly_error("include \"missing.asm\"").1, 1);
+        let message = asm::assemble("\n\n  DRW V0, V1, 16").unwrap_err().to_string();
+        assert_eq!(message, "<source>:3:15: 16 does not fit in 0xF");
+    }
+
+    #[test]
+    fn assemble_file_with_includes() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.asm"), "include \"lib/font.asm\"\nLD I, glyph\n").unwrap();
+        fs::write(dir.join("lib/font.asm"), "JP end\nglyph: db 0xF0\nend:\n  LD V0, 1 + \n").unwrap();
+        let result = asm::assemble_file(dir.join("main.asm"));
+        fs::write(dir.join("lib/font.asm"), "JP end\nglyph: db 0xF0, 0\nend:\n").unwrap();
+        let rom = asm::assemble_file(dir.join("main.asm")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(Chip8Error::Assembly { file, line, column, .. }) => {
+                assert!(file.ends_with("font.asm"));
+                assert_eq!((line, column), (4, 10));
+            }
+            other => panic!("expected an assembly error, got {:?}", other),
+        }
+        assert_eq!(rom, vec![0x12, 0x04, 0xF0, 0x00, 0xA2, 0x02]);
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let mut decoded = 0;
+        for opcode in 0..=u16::MAX {
+            if let Ok(instr) = Instruction::decode(opcode) {
+                assert_eq!(instr.encode(), opcode, "{:04X} decodes to {:?}", opcode, instr);
+                assert_eq!(Instruction::decode(instr.encode()).unwrap(), instr);
+                assert_eq!(asm::assemble(&instr.to_string()).unwrap(), instr.to_bytes(), "{}", instr);
+                decoded += 1;
+            }
+        }
+        // Every opcode of the 0, 1, 2, 3, 4, 6, 7, A, B, C and D families, plus the fixed ones
+        assert!(decoded > 11 * 0x1000 - 0x100);
+    }
+
+    #[test]
+    fn long_instruction_bytes() {
+        let instr = Instruction::LD_I_LONG(0x1234);
+        assert_eq!(instr.encode(), 0xF000);
+        assert_eq!(instr.to_bytes(), vec![0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(instr.to_bytes().len(), instr.size());
+
+        let mut cpu = cpu_with_instructions(&[instr, Instruction::LDi(0, 7), Instruction::ADD_I(0)]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.index_register(), 0x123B);
+        assert_eq!(cpu.instruction_at(0x200).unwrap(), instr);
+    }
+
+    fn traced(cpu: &mut CPU) -> Arc<Mutex<Vec<String>>> {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&lines);
+        cpu.set_trace_hook(move |entry| sink.lock().unwrap().push(entry.to_string()));
+        lines
+    }
+
+    #[test]
+    fn trace_lines() {
+        let mut cpu = cpu_with_program(&[0x6A12, 0xA2F0, 0x2208, 0x1206, 0xFA15, 0x00EE]);
+        let lines = traced(&mut cpu);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.cycles(), 4);
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "0000000001 0200 6A12 LD VA, 0x12        V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 \
+             V8=00 V9=00 VA=12 VB=00 VC=00 VD=00 VE=00 VF=00 I=0000 SP=00 DT=00 ST=00"
+        );
+        assert!(lines[2].starts_with("0000000003 0204 2208 CALL 0x208         V0=00"));
+        assert!(lines[2].ends_with("I=02F0 SP=01 DT=00 ST=00"));
+        assert!(lines[3].ends_with("I=02F0 SP=01 DT=12 ST=00"));
+    }
+
+    #[test]
+    fn trace_skips_draws_waiting_for_refresh() {
+        let mut cpu = cpu_with_quirks(&[0xD015, 0x1202], Quirks::chip8());
+        let lines = traced(&mut cpu);
+        run(&mut cpu, 3);
+        cpu.tick_timers().unwrap();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.cycles(), 1);
+        assert_eq!(lines.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn trace_hook_survives_restore() {
+        let mut cpu = cpu_with_program(&[0x7001, 0x1200]);
+        let snapshot = cpu.snapshot();
+        let saved = state_bytes(&cpu);
+        let lines = traced(&mut cpu);
+        run(&mut cpu, 2);
+        cpu.restore(&snapshot);
+        run(&mut cpu, 1);
+        cpu.load_state(&saved[..]).unwrap();
+        run(&mut cpu, 1);
+        assert_eq!(lines.lock().unwrap().len(), 4);
+        // Snapshots are not traced
+        let mut copy = CPU::new(None, test_quirks());
+        copy.restore(&cpu.snapshot());
+        run(&mut copy, 1);
+        assert_eq!(lines.lock().unwrap().len(), 4);
+
+        cpu.remove_trace_hook();
+        run(&mut cpu, 1);
+        assert_eq!(lines.lock().unwrap().len(), 4);
+    }
+
+    /// Draws the 0 glyph in the top left corner, then loops at 0x206
+    fn zero_on_screen() -> CPU {
+        cpu_with_quirks(&[0x6000, 0xF029, 0xD005, 0x1206], Quirks::chip8())
+    }
+
+    #[test]
+    fn screenshot_formats() {
+        let mut cpu = zero_on_screen();
+        // The draw waits for the refresh ending the first frame
+        assert_eq!(Headless::new(2).run(&mut cpu).unwrap(), Outcome::Timeout);
+
+        let ascii = screenshot::ascii(cpu.frame_buffer());
+        let rows: Vec<&str> = ascii.lines().collect();
+        assert_eq!(rows.len(), FRAME_BUFFER_HEIGHT);
+        assert_eq!(rows[0], format!("####{}", ".".repeat(FRAME_BUFFER_LENGTH - 4)));
+        assert!(rows[1].starts_with("#..#."));
+
+        let pbm = screenshot::pbm(cpu.frame_buffer());
+        assert!(pbm.starts_with("P1\n64 32\n1 1 1 1 0 0"));
+        assert_eq!(pbm.lines().count(), FRAME_BUFFER_HEIGHT + 2);
+
+        let png = screenshot::png(cpu.frame_buffer());
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 64, 0, 0, 0, 32]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        assert_eq!(Format::from_extension("screen.PNG"), Some(Format::Png));
+        assert_eq!(Format::from_extension("screen"), None);
+        assert!("bmp".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn parse_key_press() {
+        assert_eq!("12:a".parse::<KeyPress>().unwrap(), KeyPress { frame: 12, key: 0xA, frames: 6 });
+        assert_eq!("0:F:1".parse::<KeyPress>().unwrap(), KeyPress { frame: 0, key: 0xF, frames: 1 });
+        assert!(matches!("1:10".parse::<KeyPress>(), Err(Chip8Error::InvalidKey { key: 0x10 })));
+        assert!("1".parse::<KeyPress>().is_err());
+        assert!("1:2:3:4".parse::<KeyPress>().is_err());
+    }
+
+    #[test]
+    fn headless_stop_conditions() {
+        let mut headless = Headless::new(10);
+        headless.set_until(Until::Loop);
+        assert_eq!(headless.run(&mut zero_on_screen()).unwrap(), Outcome::Reached { frame: 1 });
+
+        let mut headless = Headless::new(10);
+        headless.set_until(Until::Pc(0x204));
+        let mut cpu = zero_on_screen();
+        assert_eq!(headless.run(&mut cpu).unwrap(), Outcome::Reached { frame: 0 });
+        assert_eq!(cpu.program_counter(), 0x204);
+
+        headless.set_until(Until::Pc(0x300));
+        assert_eq!(headless.run(&mut zero_on_screen()).unwrap(), Outcome::Timeout);
+
+        // EXIT
+        let mut cpu = cpu_with_quirks(&[0x00FD], Quirks::superchip());
+        assert_eq!(headless.run(&mut cpu).unwrap(), Outcome::Exited { frame: 0 });
+    }
+
+    #[test]
+    fn headless_key_script() {
+        // LD V1, K ; JP 0x202
+        let mut headless = Headless::new(20);
+        headless.add_key_press(KeyPress { frame: 3, key: 0xC, frames: 2 });
+        assert!(!headless.keys(2)[0xC]);
+        assert!(headless.keys(4)[0xC]);
+        assert!(!headless.keys(5)[0xC]);
+
+        headless.set_until(Until::Loop);
+        let mut cpu = cpu_with_program(&[0xF10A, 0x1202]);
+        assert_eq!(headless.run(&mut cpu).unwrap(), Outcome::Reached { frame: 5 });
+        assert_eq!(cpu.register(1), 0xC);
+    }
+
+    /// V0 to V7 once random_program filled them
+    fn random_registers(cpu: &mut CPU) -> Vec<VValue> {
+        run(cpu, 8);
+        (0..8).map(|index| cpu.register(index)).collect()
+    }
+
+    fn random_program() -> Vec<u16> {
+        (0..8).map(|index| Instruction::RNDi(index, 0xFF).encode()).chain([0x1210]).collect()
+    }
+
+    #[test]
+    fn splitmix_reference_values() {
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+        let mut resumed = SplitMix64::resumed(0, 2);
+        assert_eq!(resumed.next_u64(), rng.next_u64());
+    }
+
+    #[test]
+    fn seed_makes_rnd_reproducible() {
+        let mut cpu = cpu_with_program(&random_program());
+        cpu.set_seed(42);
+        assert_eq!(cpu.seed(), Some(42));
+        let first = random_registers(&mut cpu);
+
+        let mut cpu = cpu_with_program(&random_program());
+        cpu.set_seed(42);
+        assert_eq!(random_registers(&mut cpu), first);
+
+        let mut cpu = cpu_with_program(&random_program());
+        cpu.set_seed(43);
+        assert_ne!(random_registers(&mut cpu), first);
+    }
+
+    #[test]
+    fn restored_states_draw_the_same_numbers() {
+        let mut cpu = cpu_with_program(&[0xC0FF, 0x1200]);
+        cpu.set_seed(7);
+        run(&mut cpu, 10);
+        let saved = state_bytes(&cpu);
+        let snapshot = cpu.snapshot();
+        let next: Vec<VValue> = (0..5).map(|_| { run(&mut cpu, 2); cpu.register(0) }).collect();
+
+        cpu.restore(&snapshot);
+        let replayed: Vec<VValue> = (0..5).map(|_| { run(&mut cpu, 2); cpu.register(0) }).collect();
+        assert_eq!(replayed, next);
+
+        let mut loaded = CPU::new(None, test_quirks());
+        loaded.load_state(&saved[..]).unwrap();
+        assert_eq!(loaded.seed(), Some(7));
+        let replayed: Vec<VValue> = (0..5).map(|_| { run(&mut loaded, 2); loaded.register(0) }).collect();
+        assert_eq!(replayed, next);
+    }
+
+    #[test]
+    fn host_random_source() {
+        let mut cpu = cpu_with_program(&[0xC0F0, 0x1200]);
+        cpu.set_random_source(StepRng::new(0x4321, 0));
+        assert_eq!(cpu.seed(), None);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(0), 0x20);
+
+        // Save states can't hold the host source, loading keeps the current one
+        let saved = state_bytes(&cpu);
+        let mut loaded = CPU::new(None, test_quirks());
+        loaded.set_random_source(StepRng::new(0x0F, 0));
+        loaded.load_state(&saved[..]).unwrap();
+        assert_eq!(loaded.seed(), None);
+        run(&mut loaded, 2);
+        assert_eq!(loaded.register(0), 0x00);
+    }
+
+    /// Waits for a key then draws its glyph at a random place, forever
+    fn names(keymap: &KeyMap, key: u8) -> Vec<&str> {
+        keymap.bindings(key).iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn keymap_defaults_to_qwerty() {
+        let keymap = KeyMap::from_toml("", Some("pong.ch8")).unwrap();
+        assert_eq!(keymap, KeyMap::qwerty());
+        assert_eq!(names(&keymap, 0x1), vec!["1"]);
+        assert_eq!(names(&keymap, 0xC), vec!["4"]);
+        assert_eq!(names(&keymap, 0x0), vec!["X"]);
+        assert_eq!(names(&keymap, 0xF), vec!["V"]);
+        assert!(keymap.bindings(0x10).is_empty());
+    }
+
+    #[test]
+    fn keymap_applies_rom_overrides() {
+        let config = r#"
+            [keys]
+            4 = "A"
+            5 = ["Z", "Up"]
+            7 = []
+
+            [rom."pong.ch8"]
+            1 = ["Z", "Keypad 8"]
+            5 = "W"
+        "#;
+        let global = KeyMap::from_toml(config, Some("other.ch8")).unwrap();
+        assert_eq!(names(&global, 0x4), vec!["A"]);
+        assert_eq!(names(&global, 0x5), vec!["Z", "Up"]);
+        assert!(names(&global, 0x7).is_empty());
+        assert_eq!(names(&global, 0x1), vec!["1"]);
+
+        let pong = KeyMap::from_toml(config, Some("pong.ch8")).unwrap();
+        assert_eq!(names(&pong, 0x1), vec!["Z", "Keypad 8"]);
+        assert_eq!(names(&pong, 0x5), vec!["W"]);
+        assert_eq!(names(&pong, 0x4), vec!["A"]);
+        assert_eq!(KeyMap::from_toml(config, None).unwrap(), global);
+    }
+
+    #[test]
+    fn keymap_prints_as_toml() {
+        let mut keymap = KeyMap::qwerty();
+        keymap.bind(0xA, vec!["Left Shift".to_string(), "\\".to_string()]).unwrap();
+        let printed = keymap.to_string();
+        assert!(printed.starts_with("[keys]\n0 = [\"X\"]\n"));
+        assert_eq!(KeyMap::from_toml(&printed, None).unwrap(), keymap);
+        assert!(matches!(keymap.bind(0x10, Vec::new()), Err(Chip8Error::InvalidKey { key: 0x10 })));
+    }
+
+    #[test]
+    fn keymap_rejects_bad_config() {
+        for config in [
+            "[keys",
+            "[keys]\nG = \"A\"",
+            "[keys]\n10 = \"A\"",
+            "[keys]\n1 = 2",
+            "[keys]\n1 = [\"A\", 2]",
+            "[keys]\n1 = \"\"",
+            "[key]\n1 = \"A\"",
+            "[rom]\n\"pong.ch8\" = \"A\"",
+            "[rom.\"pong.ch8\"]\nX = \"A\"",
+        ] {
+            assert!(
+                matches!(KeyMap::from_toml(config, Some("other.ch8")), Err(Chip8Error::InvalidKeyMap(_))),
+                "{:?} should be rejected", config
+            );
+        }
+    }
+
+    fn movie_program() -> CPU {
+        let mut cpu = cpu_with_program(&[0xF20A, 0xF229, 0xC03F, 0xC11F, 0xD015, 0x1200]);
+        cpu.set_seed(1234);
+        cpu
+    }
+
+    /// `frames` frames with 5 then A typed
+    fn record_movie(cpu: &mut CPU, frames: usize) -> Movie {
+        let mut movie = Movie::record(cpu).unwrap();
+        for frame in 0..frames {
+            let mut keys = [false; KEY_COUNT];
+            keys[0x5] = (10..15).contains(&frame);
+            keys[0xA] = (30..35).contains(&frame);
+            cpu.set_keys(&keys);
+            cpu.run_frame(cpu.cycles_per_frame()).unwrap();
+            movie.push_frame(&keys, cpu);
+        }
+        movie
+    }
+
+    #[test]
+    fn movie_replays_recorded_session() {
+        let mut recorded = movie_program();
+        let movie = record_movie(&mut recorded, 120);
+        assert_eq!(movie.len(), 120);
+        assert_eq!(lit_pixels(&recorded).len(), 14 + 14);
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        let movie = Movie::read(&bytes[..]).unwrap();
+        assert_eq!(movie.seed(), 1234);
+
+        // A fresh CPU with another seed still replays identically
+        let mut cpu = cpu_with_program(&[0xF20A, 0xF229, 0xC03F, 0xC11F, 0xD015, 0x1200]);
+        movie.start_replay(&mut cpu).unwrap();
+        let mut headless = Headless::new(movie.len() as u64);
+        headless.set_movie(movie);
+        assert_eq!(headless.run(&mut cpu).unwrap(), Outcome::Timeout);
+        assert_eq!(screenshot::ascii(cpu.frame_buffer()), screenshot::ascii(recorded.frame_buffer()));
+    }
+
+    #[test]
+    fn movie_detects_other_rom_and_desync() {
+        let movie = record_movie(&mut movie_program(), 120);
+
+        let mut cpu = cpu_with_program(&[0xF20A, 0x1200]);
+        assert!(matches!(movie.start_replay(&mut cpu), Err(Chip8Error::RomMismatch { .. })));
+        let mut cpu = CPU::new(Some(600), test_quirks());
+        assert!(matches!(movie.start_replay(&mut cpu), Err(Chip8Error::InvalidMovie(_))));
+
+        let mut cpu = movie_program();
+        movie.start_replay(&mut cpu).unwrap();
+        cpu.set_seed(99);
+        let mut headless = Headless::new(movie.len() as u64);
+        headless.set_movie(movie);
+        assert!(matches!(headless.run(&mut cpu), Err(Chip8Error::Desync { frame: 59 })));
+
+        // Shorter than CHECKSUM_INTERVAL, only the final checksum catches it
+        let movie = record_movie(&mut movie_program(), 40);
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        let movie = Movie::read(&bytes[..]).unwrap();
+        let mut cpu = movie_program();
+        movie.start_replay(&mut cpu).unwrap();
+        cpu.set_seed(99);
+        let mut headless = Headless::new(movie.len() as u64);
+        headless.set_movie(movie);
+        assert!(matches!(headless.run(&mut cpu), Err(Chip8Error::Desync { frame: 39 })));
+    }
+
+    #[test]
+    fn movie_rejects_bad_input() {
+        let movie = record_movie(&mut movie_program(), 120);
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+
+        assert!(matches!(Movie::read(&b"C8ST"[..]), Err(Chip8Error::InvalidMovie(_))));
+        assert!(matches!(Movie::read(&bytes[..bytes.len() - 1]), Err(Chip8Error::Io(_))));
+        let mut cpu = movie_program();
+        cpu.set_random_source(StepRng::new(0, 1));
+        assert!(matches!(Movie::record(&cpu), Err(Chip8Error::InvalidMovie(_))));
+    }
+
+    /// Program under test and the machine state it starts from, e.g.
+    /// `program(&[ADD(0, 1)]).v(0, 0xFF).v(1, 1).run_all()`
+    struct Machine {
+        program: Vec<Instruction>,
+        quirks: Quirks,
+        registers: Vec<(VIndex, VValue)>,
+        index: Addr,
+        memory: Vec<(Addr, Vec<CellValue>)>,
+        keys: Vec<u8>,
+    }
+
+    fn program(program: &[Instruction]) -> Machine {
+        Machine {
+            program: program.to_vec(),
+            quirks: test_quirks(),
+            registers: Vec::new(),
+            index: 0,
+            memory: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    impl Machine {
+        fn quirks(mut self, quirks: Quirks) -> Machine {
+            self.quirks = quirks;
+            self
+        }
+
+        fn v(mut self, index: VIndex, value: VValue) -> Machine {
+            self.registers.push((index, value));
+            self
+        }
+
+        fn i(mut self, addr: Addr) -> Machine {
+            self.index = addr;
+            self
+        }
+
+        fn memory(mut self, addr: Addr, bytes: &[CellValue]) -> Machine {
+            self.memory.push((addr, bytes.to_vec()));
+            self
+        }
+
+        fn key(mut self, key: u8) -> Machine {
+            self.keys.push(key);
+            self
+        }
+
+        /// Powered on CPU, PC on the first instruction
+        fn cpu(&self) -> CPU {
+            let rom: Vec<u8> = self.program.iter().flat_map(Instruction::to_bytes).collect();
+            let mut cpu = CPU::new(Some(6000), self.quirks);
+            cpu.load_rom(&rom).unwrap();
+            cpu.power_on();
+            for (index, value) in self.registers.iter() {
+                cpu.set_register(*index, *value).unwrap();
+            }
+            cpu.set_index_register(self.index);
+            for (addr, bytes) in self.memory.iter() {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    cpu.write_memory(addr + offset, *byte).unwrap();
+                }
+            }
+            for key in self.keys.iter() {
+                cpu.press_key(*key).unwrap();
+            }
+            cpu
+        }
+
+        fn run(&self, instructions: usize) -> CPU {
+            let mut cpu = self.cpu();
+            run(&mut cpu, instructions);
+            cpu
+        }
+
+        /// One step per instruction of the program
+        fn run_all(&self) -> CPU {
+            self.run(self.program.len())
+        }
+
+        fn error(&self, instructions: usize) -> Chip8Error {
+            run_until_error(&mut self.cpu(), instructions)
+        }
+    }
+
+    mod opcodes {
+        use super::*;
+        use crate::cpu::Instruction::*;
+
+        const DATA: Addr = 0x300;
+
+        #[test]
+        fn sys_is_not_supported() {
+            let error = program(&[SYS(0x123)]).error(1);
+            assert!(matches!(error, Chip8Error::IllegalOpcode { opcode: 0x0123, pc: 0x200 }));
+        }
+
+        #[test]
+        fn cls_clears_the_screen() {
+            let machine = program(&[DRW(0, 0, 1), CLS]).i(DATA).memory(DATA, &[0xFF]);
+            assert_eq!(lit_pixels(&machine.run(1)).len(), 8);
+            assert!(lit_pixels(&machine.run_all()).is_empty());
+        }
+
+        #[test]
+        fn jp() {
+            assert_eq!(program(&[JP(0x208)]).run_all().program_counter(), 0x208);
+        }
+
+        #[test]
+        fn call_and_ret() {
+            // 0x200 CALL 0x206 ; 0x202 LD V1, 1 ; 0x204 JP 0x204 ; 0x206 RET
+            let machine = program(&[CALL(0x206), LDi(1, 1), JP(0x204), RET]);
+            let cpu = machine.run(1);
+            assert_eq!(cpu.program_counter(), 0x206);
+            assert_eq!(cpu.stack(), &[0x202]);
+
+            let cpu = machine.run(3);
+            assert!(cpu.stack().is_empty());
+            assert_eq!(cpu.register(1), 1);
+        }
+
+        #[test]
+        fn call_at_stack_depth() {
+            let machine = program(&[CALL(0x200)]);
+            let cpu = machine.run(STACK_SIZE);
+            assert_eq!(cpu.stack(), &[0x202; STACK_SIZE]);
+            assert!(matches!(machine.error(STACK_SIZE + 1), Chip8Error::StackOverflow));
+        }
+
+        #[test]
+        fn ret_on_empty_stack() {
+            assert!(matches!(program(&[RET]).error(1), Chip8Error::StackUnderflow));
+        }
+
+        #[test]
+        fn jp_v0() {
+            let cpu = program(&[JP_V0(0x300)]).v(0, 0x10).v(3, 0x04).run_all();
+            assert_eq!(cpu.program_counter(), 0x310);
+
+            let cpu = program(&[JP_V0(0x300)]).v(0, 0x10).v(3, 0x04).quirks(Quirks::superchip()).run_all();
+            assert_eq!(cpu.program_counter(), 0x304);
+        }
+
+        #[test]
+        fn skips_on_registers() {
+            let pc = |instr: Instruction| program(&[instr]).v(0, 5).v(1, 5).v(2, 6).run_all().program_counter();
+            assert_eq!(pc(SEi(0, 5)), 0x204);
+            assert_eq!(pc(SEi(0, 6)), 0x202);
+            assert_eq!(pc(SNEi(0, 6)), 0x204);
+            assert_eq!(pc(SNEi(0, 5)), 0x202);
+            assert_eq!(pc(SE(0, 1)), 0x204);
+            assert_eq!(pc(SE(0, 2)), 0x202);
+            assert_eq!(pc(SNE(0, 2)), 0x204);
+            assert_eq!(pc(SNE(0, 1)), 0x202);
+        }
+
+        #[test]
+        fn skip_over_long_load() {
+            let cpu = program(&[SEi(0, 0), LD_I_LONG(0x1234), LDi(1, 1)]).quirks(Quirks::xochip()).run(2);
+            assert_eq!(cpu.program_counter(), 0x208);
+            assert_eq!(cpu.register(1), 1);
+        }
+
+        #[test]
+        fn ld_immediate_and_register() {
+            let cpu = program(&[LDi(0xE, 0x42), LD(3, 0xE)]).run_all();
+            assert_eq!(cpu.register(0xE), 0x42);
+            assert_eq!(cpu.register(3), 0x42);
+        }
+
+        #[test]
+        fn add_immediate_wraps_without_flag() {
+            let cpu = program(&[ADDi(0, 2)]).v(0, 0xFF).v(VF, 0x42).run_all();
+            assert_eq!(cpu.register(0), 0x01);
+            assert_eq!(cpu.register(VF), 0x42);
+        }
+
+        #[test]
+        fn logical_operations() {
+            let machine = |instr: Instruction| program(&[instr]).v(0, 0b1100).v(1, 0b1010).v(VF, 0x42);
+            for (instr, result) in [(OR(0, 1), 0b1110), (AND(0, 1), 0b1000), (XOR(0, 1), 0b0110)] {
+                let cpu = machine(instr).run_all();
+                assert_eq!(cpu.register(0), result, "{}", instr);
+                assert_eq!(cpu.register(VF), 0, "{}", instr);
+
+                let cpu = machine(instr).quirks(Quirks::superchip()).run_all();
+                assert_eq!(cpu.register(0), result, "{}", instr);
+                assert_eq!(cpu.register(VF), 0x42, "{}", instr);
+            }
+        }
+
+        #[test]
+        fn add_carry_at_0xff() {
+            let cpu = program(&[ADD(0, 1)]).v(0, 0xFE).v(1, 0x01).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0xFF, 0));
+
+            let cpu = program(&[ADD(0, 1)]).v(0, 0xFF).v(1, 0x01).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0x00, 1));
+
+            let cpu = program(&[ADD(0, 1)]).v(0, 0xFF).v(1, 0xFF).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0xFE, 1));
+        }
+
+        #[test]
+        fn sub_borrow_at_equality() {
+            let cpu = program(&[SUB(0, 1)]).v(0, 0x07).v(1, 0x07).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0x00, 1));
+
+            let cpu = program(&[SUB(0, 1)]).v(0, 0x06).v(1, 0x07).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0xFF, 0));
+
+            let cpu = program(&[SUBN(0, 1)]).v(0, 0x07).v(1, 0x07).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0x00, 1));
+
+            let cpu = program(&[SUBN(0, 1)]).v(0, 0x08).v(1, 0x07).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0xFF, 0));
+        }
+
+        #[test]
+        fn shifts() {
+            let machine = |instr: Instruction| program(&[instr]).v(0, 0b1000_0001).v(1, 0b0100_0010);
+            let cpu = machine(SHR(0, 1)).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0b0010_0001, 0));
+            let cpu = machine(SHL(0, 1)).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0b1000_0100, 0));
+
+            let cpu = machine(SHR(0, 1)).quirks(Quirks::superchip()).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0b0100_0000, 1));
+            let cpu = machine(SHL(0, 1)).quirks(Quirks::superchip()).run_all();
+            assert_eq!((cpu.register(0), cpu.register(VF)), (0b0000_0010, 1));
+        }
+
+        #[test]
+        fn index_register() {
+            let cpu = program(&[LD_I(0x123)]).run_all();
+            assert_eq!(cpu.index_register(), 0x123);
+
+            // No wrap around and no carry in VF
+            let cpu = program(&[ADD_I(0)]).i(0xFFE).v(0, 0x03).run_all();
+            assert_eq!(cpu.index_register(), 0x1001);
+            assert_eq!(cpu.register(VF), 0);
+
+            let cpu = program(&[LD_I_LONG(0xBEEF)]).quirks(Quirks::xochip()).run_all();
+            assert_eq!(cpu.index_register(), 0xBEEF);
+            assert_eq!(cpu.program_counter(), 0x204);
+        }
+
+        #[test]
+        fn rnd_masks_random_byte() {
+            assert_eq!(program(&[RNDi(0, 0x00)]).v(0, 0x42).run_all().register(0), 0);
+            for _ in 0..32 {
+                assert_eq!(program(&[RNDi(0, 0x0F)]).run_all().register(0) & 0xF0, 0);
+            }
+        }
+
+        #[test]
+        fn drw_wrapping_at_screen_edges() {
+            // Two rows of 8 pixels at (60, 31)
+            let machine = program(&[DRW(0, 1, 2)]).v(0, 60).v(1, 31).i(DATA).memory(DATA, &[0xFF, 0xFF]);
+            let cpu = machine.run_all();
+            assert_eq!(lit_pixels(&cpu), vec![(31, 60), (31, 61), (31, 62), (31, 63)]);
+
+            let cpu = machine.quirks(Quirks::xochip()).run_all();
+            let corners: Vec<(usize, usize)> = [0, 31].iter()
+                .flat_map(|y| [0, 1, 2, 3, 60, 61, 62, 63].iter().map(move |x| (*y, *x)))
+                .collect();
+            assert_eq!(lit_pixels(&cpu), corners);
+        }
+
+        #[test]
+        fn drw_origin_always_wraps() {
+            let cpu = program(&[DRW(0, 1, 1)]).v(0, 64 + 2).v(1, 32 + 3).i(DATA).memory(DATA, &[0x80]).run_all();
+            assert_eq!(lit_pixels(&cpu), vec![(3, 2)]);
+        }
+
+        #[test]
+        fn drw_collision_resets_vf() {
+            let machine = program(&[DRW(0, 0, 1), DRW(0, 0, 1), DRW(0, 0, 1)]).i(DATA).memory(DATA, &[0x80]);
+            assert_eq!(machine.run(1).register(VF), 0);
+            assert_eq!(machine.run(2).register(VF), 1);
+            assert_eq!(machine.run(3).register(VF), 0);
+        }
+
+        #[test]
+        fn key_skips_use_low_nibble() {
+            let pc = |instr: Instruction| program(&[instr]).v(0, 0x1A).key(0xA).run_all().program_counter();
+            assert_eq!(pc(SKP(0)), 0x204);
+            assert_eq!(pc(SKNP(0)), 0x202);
+            let cpu = program(&[SKP(0), SKNP(0)]).v(0, 0x0B).run_all();
+            assert_eq!(cpu.program_counter(), 0x206);
+        }
+
+        #[test]
+        fn ld_k_stores_released_key() {
+            let mut cpu = program(&[LD_K(4)]).cpu();
+            run(&mut cpu, 1);
+            cpu.press_key(0x9).unwrap();
+            run(&mut cpu, 1);
+            cpu.release_key(0x9).unwrap();
+            run(&mut cpu, 1);
+            assert_eq!(cpu.register(4), 0x9);
+            assert_eq!(cpu.program_counter(), 0x202);
+        }
+
+        #[test]
+        fn timers() {
+            let cpu = program(&[SET_DT(0), LD_DT(1), SET_ST(2)]).v(0, 0x33).v(2, 0x44).run_all();
+            assert_eq!(cpu.register(1), 0x33);
+            assert_eq!(cpu.delay_timer().get().unwrap(), 0x33);
+            assert_eq!(cpu.sound_timer().get().unwrap(), 0x44);
+        }
+
+        #[test]
+        fn font_glyphs() {
+            let cpu = program(&[LD_F(0)]).v(0, 0x1B).run_all();
+            assert_eq!(cpu.index_register(), cpu.font_address() + 0xB * FONT_GLYPH_SIZE);
+
+            let cpu = program(&[LD_HF(0)]).v(0, 0x13).quirks(Quirks::superchip()).run_all();
+            assert_eq!(cpu.index_register(), cpu.big_font_address() + 0x3 * BIG_FONT_GLYPH_SIZE);
+        }
+
+        #[test]
+        fn ld_b_on_0_and_255() {
+            for (value, digits) in [(0, [0, 0, 0]), (255, [2, 5, 5]), (137, [1, 3, 7]), (9, [0, 0, 9])] {
+                let cpu = program(&[LD_B(5)]).v(5, value).i(DATA).memory(DATA, &[0xAA; 4]).run_all();
+                let written: Vec<CellValue> = (0..4).map(|offset| cpu.read_memory(DATA + offset).unwrap()).collect();
+                assert_eq!(written, [digits[0], digits[1], digits[2], 0xAA], "LD B, {}", value);
+                assert_eq!(cpu.index_register(), DATA);
+            }
+        }
+
+        #[test]
+        fn store_and_load_registers() {
+            let machine = program(&[ST_UNTIL(2)]).v(0, 1).v(1, 2).v(2, 3).v(3, 4).i(DATA);
+            let cpu = machine.run_all();
+            let stored: Vec<CellValue> = (0..4).map(|offset| cpu.read_memory(DATA + offset).unwrap()).collect();
+            assert_eq!(stored, [1, 2, 3, 0]);
+            assert_eq!(cpu.index_register(), DATA + 3);
+            assert_eq!(machine.quirks(Quirks::superchip()).run_all().index_register(), DATA);
+
+            let machine = program(&[LD_UNTIL(1)]).v(2, 0x42).i(DATA).memory(DATA, &[7, 8, 9]);
+            let cpu = machine.run_all();
+            assert_eq!([cpu.register(0), cpu.register(1), cpu.register(2)], [7, 8, 0x42]);
+            assert_eq!(cpu.index_register(), DATA + 2);
+            assert_eq!(machine.quirks(Quirks::superchip()).run_all().index_register(), DATA);
+        }
+
+        #[test]
+        fn register_ranges() {
+            let cpu = program(&[ST_RANGE(3, 1)]).v(1, 1).v(2, 2).v(3, 3).i(DATA).quirks(Quirks::xochip()).run_all();
+            let stored: Vec<CellValue> = (0..3).map(|offset| cpu.read_memory(DATA + offset).unwrap()).collect();
+            assert_eq!(stored, [3, 2, 1]);
+            assert_eq!(cpu.index_register(), DATA);
+
+            let cpu = program(&[LD_RANGE(1, 2)]).i(DATA).memory(DATA, &[5, 6]).quirks(Quirks::xochip()).run_all();
+            assert_eq!([cpu.register(1), cpu.register(2)], [5, 6]);
+        }
+
+        #[test]
+        fn rpl_flags() {
+            let cpu = program(&[ST_R(2), LDi(0, 0), LDi(1, 0), LD_R(1)])
+                .v(0, 1).v(1, 2).v(2, 3).quirks(Quirks::superchip()).run_all();
+            assert_eq!(cpu.rpl_flags()[..4], [1, 2, 3, 0]);
+            assert_eq!([cpu.register(0), cpu.register(1)], [1, 2]);
+        }
+
+        #[test]
+        fn scrolls() {
+            let machine = |scroll: Instruction| {
+                program(&[DRW(0, 0, 1), scroll]).v(0, 8).i(DATA).memory(DATA, &[0x80]).quirks(Quirks::superchip())
+            };
+            assert_eq!(lit_pixels(&machine(SCD(3)).run_all()), vec![(11, 8)]);
+            assert_eq!(lit_pixels(&machine(SCR).run_all()), vec![(8, 12)]);
+            assert_eq!(lit_pixels(&machine(SCL).run_all()), vec![(8, 4)]);
+        }
+
+        #[test]
+        fn resolution() {
+            let cpu = program(&[HIGH]).quirks(Quirks::superchip()).run_all();
+            assert_eq!(cpu.frame_buffer().width(), HIRES_FRAME_BUFFER_LENGTH);
+            assert_eq!(cpu.frame_buffer().height(), HIRES_FRAME_BUFFER_HEIGHT);
+
+            let cpu = program(&[HIGH, LOW]).quirks(Quirks::superchip()).run_all();
+            assert_eq!(cpu.frame_buffer().width(), FRAME_BUFFER_LENGTH);
+            assert_eq!(cpu.frame_buffer().height(), FRAME_BUFFER_HEIGHT);
+        }
+
+        #[test]
+        fn exit() {
+            let cpu = program(&[EXIT]).quirks(Quirks::superchip()).run_all();
+            assert!(!cpu.is_on());
+            assert_eq!(cpu.program_counter(), 0x200);
+        }
+
+        #[test]
+        fn xochip_plane_audio_and_pitch() {
+            let pattern: Vec<CellValue> = (0..AUDIO_PATTERN_SIZE as u8).collect();
+            let cpu = program(&[PLANE(3), AUDIO, PITCH(0)])
+                .v(0, 0x70).i(DATA).memory(DATA, &pattern).quirks(Quirks::xochip()).run_all();
+            assert_eq!(cpu.selected_planes(), 3);
+            assert_eq!(&cpu.audio_pattern()[..], &pattern[..]);
+            assert_eq!(cpu.pitch(), 0x70);
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::fmt;
+use std::fmt::Write;
+
+use crate::cpu::{CPU, Addr, VIndex, VValue, CellValue, Instruction, GP_REGISTERS_COUNT};
+use crate::error::Chip8Error;
+
+/// Instructions shown before and after PC by `dis` without an address
+const DISASSEMBLY_CONTEXT: usize = 4;
+const HEXDUMP_DEFAULT_LENGTH: usize = 64;
+const HEXDUMP_ROW: usize = 16;
+
+const HELP: &str = "\
+Addresses and opcode patterns are hexadecimal, counts are decimal.
+  s, step [N]             execute N instructions, 1 by default
+  n, next                 step over CALL
+  c, continue             run until a breakpoint or a watchpoint
+  p, pause                stop execution
+  b, break ADDR           break when PC reaches ADDR
+  bo, breakop PATTERN     break before opcodes matching PATTERN, e.g. D??5 or 8xy4
+  w, watch ADDR [END]     stop when memory from ADDR to END included changes
+  w, watch VX             stop when register VX changes
+  l, list                 list breakpoints and watchpoints
+  d, delete [N]           delete breakpoint or watchpoint N, all of them without N
+  r, regs                 print registers, stack and timers
+  x ADDR [LEN]            hexdump LEN bytes of memory from ADDR
+  dis [ADDR] [N]          disassemble N instructions from ADDR, around PC by default
+  q, quit                 leave the emulator
+An empty line repeats the previous command.";
+
+/// Stops execution before the instruction at an address, or before an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    Address(Addr),
+    /// Opcodes equal to `pattern` once masked by `mask`
+    Opcode { pattern: u16, mask: u16 },
+}
+
+impl Breakpoint {
+
+    /// Parses an opcode pattern, any character that is not a hexadecimal digit matching any nibble
+    pub fn opcode(pattern: &str) -> Result<Breakpoint, Chip8Error> {
+        if pattern.chars().count() != 4 {
+            return Err(Chip8Error::InvalidCommand(format!("{:?} is not a 4 nibbles opcode pattern", pattern)))
+        }
+        let (mut value, mut mask) = (0, 0);
+        for c in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(nibble) = c.to_digit(16) {
+                value |= nibble as u16;
+                mask |= 0xF;
+            }
+        }
+        Ok(Breakpoint::Opcode { pattern: value, mask })
+    }
+
+    fn hit(&self, pc: Addr, opcode: u16) -> bool {
+        match *self {
+            Breakpoint::Address(addr) => addr == pc,
+            Breakpoint::Opcode { pattern, mask } => opcode & mask == pattern,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Breakpoint::Address(addr) => write!(f, "break at {:#05X}", addr),
+            Breakpoint::Opcode { pattern, mask } => {
+                write!(f, "break on opcode ")?;
+                for shift in [12, 8, 4, 0].iter() {
+                    if (mask >> shift) & 0xF == 0 {
+                        write!(f, "?")?;
+                    }
+                    else {
+                        write!(f, "{:X}", (pattern >> shift) & 0xF)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Stops execution after an instruction changed a watched value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// Memory from `start` to `end` included
+    Memory { start: Addr, end: Addr },
+    Register(VIndex),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory { start, end } if start == end => write!(f, "watch {:#05X}", start),
+            Watchpoint::Memory { start, end } => write!(f, "watch {:#05X}..={:#05X}", start, end),
+            Watchpoint::Register(index) => write!(f, "watch V{:X}", index),
+        }
+    }
+}
+
+/// Why the debugger paused the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Step or step over done
+    Stepped,
+    Breakpoint { pc: Addr, opcode: u16 },
+    MemoryChanged { addr: Addr, old: CellValue, new: CellValue },
+    RegisterChanged { index: VIndex, old: VValue, new: VValue },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StopReason::Stepped => write!(f, "Stepped"),
+            StopReason::Breakpoint { pc, opcode } => write!(f, "Breakpoint at {:#05X}, opcode {:04X}", pc, opcode),
+            StopReason::MemoryChanged { addr, old, new } => {
+                write!(f, "Memory at {:#05X} changed from {:#04X} to {:#04X}", addr, old, new)
+            }
+            StopReason::RegisterChanged { index, old, new } => {
+                write!(f, "V{:X} changed from {:#04X} to {:#04X}", index, old, new)
+            }
+        }
+    }
+}
+
+/// Breakpoints, watchpoints and stepping on top of a CPU, driven by text commands
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    paused: bool,
+    /// Instructions left before a step stops
+    steps: Option<usize>,
+    /// Return address and stack depth a step over waits for
+    step_over: Option<(Addr, usize)>,
+    /// Don't stop again on the breakpoint execution resumes from
+    resuming: bool,
+    last_command: String,
+}
+
+impl Debugger {
+
+    /// Starts paused, so breakpoints can be set before the program runs
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            paused: true,
+            steps: None,
+            step_over: None,
+            resuming: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.steps = None;
+        self.step_over = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Runs up to `cycles` instructions then ticks the timers, as CPU::run_frame,
+    /// unless execution stops before the end of the frame. Does nothing while paused
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles: usize) -> Result<Option<StopReason>, Chip8Error> {
+        if self.paused {
+            return Ok(None)
+        }
+        for _ in 0..cycles {
+            if let Some(reason) = self.debug_step(cpu)? {
+                self.pause();
+                // The frame still went by, only steps stop between two instructions
+                if reason != StopReason::Stepped {
+                    cpu.tick_timers()?;
+                }
+                return Ok(Some(reason))
+            }
+            if cpu.is_waiting_for_vblank() {
+                break;
+            }
+        }
+        cpu.tick_timers()?;
+        Ok(None)
+    }
+
+    /// Executes one instruction, watching breakpoints, watchpoints and pending steps
+    fn debug_step(&mut self, cpu: &mut CPU) -> Result<Option<StopReason>, Chip8Error> {
+        let pc = cpu.program_counter();
+        let opcode = opcode_at(cpu, pc);
+        // Instructions waiting for a key or the refresh were already stopped on
+        let stopping = !self.resuming && !cpu.is_waiting_for_key() && !cpu.is_waiting_for_vblank();
+        if stopping && self.breakpoints.iter().any(|b| b.hit(pc, opcode)) {
+            return Ok(Some(StopReason::Breakpoint { pc, opcode }))
+        }
+        self.resuming = false;
+
+        let before = self.watched_values(cpu);
+        if let Err(e) = cpu.step() {
+            self.pause();
+            return Err(e)
+        }
+        if let Some(reason) = self.changed_value(cpu, &before) {
+            return Ok(Some(reason))
+        }
+
+        if let Some(steps) = self.steps.as_mut() {
+            // A draw waiting for the refresh completes in the next frame
+            if !cpu.is_waiting_for_key() && !cpu.is_waiting_for_vblank() {
+                *steps -= 1;
+            }
+            if *steps == 0 {
+                return Ok(Some(StopReason::Stepped))
+            }
+        }
+        if let Some((addr, depth)) = self.step_over {
+            if cpu.program_counter() == addr && cpu.stack().len() == depth {
+                return Ok(Some(StopReason::Stepped))
+            }
+        }
+        Ok(None)
+    }
+
+    fn watched_values(&self, cpu: &CPU) -> Vec<Vec<u8>> {
+        self.watchpoints.iter().map(|watchpoint| match *watchpoint {
+            Watchpoint::Memory { start, end } => {
+                (start..=end).map(|addr| cpu.read_memory(addr).unwrap_or(0)).collect()
+            }
+            Watchpoint::Register(index) => vec![cpu.register(index)],
+        }).collect()
+    }
+
+    fn changed_value(&self, cpu: &CPU, before: &[Vec<u8>]) -> Option<StopReason> {
+        let after = self.watched_values(cpu);
+        for ((watchpoint, old), new) in self.watchpoints.iter().zip(before).zip(after.iter()) {
+            if let Some(offset) = (0..old.len()).find(|i| old[*i] != new[*i]) {
+                return Some(match *watchpoint {
+                    Watchpoint::Memory { start, .. } => {
+                        StopReason::MemoryChanged { addr: start + offset, old: old[offset], new: new[offset] }
+                    }
+                    Watchpoint::Register(index) => {
+                        StopReason::RegisterChanged { index, old: old[0], new: new[0] }
+                    }
+                })
+            }
+        }
+        None
+    }
+
+    /// Runs a REPL command and returns what to print. Stepping commands only resume
+    /// execution, the stop is reported by run_frame
+    pub fn command(&mut self, cpu: &mut CPU, line: &str) -> Result<String, Chip8Error> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(String::new()),
+        };
+
+        match (command, args) {
+            ("s", _) | ("step", _) => {
+                let count = match args.first() {
+                    Some(count) => parse_count(count)?,
+                    None => 1,
+                };
+                self.steps = Some(count.max(1));
+                self.resume();
+                Ok(String::new())
+            }
+            ("n", []) | ("next", []) => {
+                let pc = cpu.program_counter();
+                match cpu.instruction_at(pc) {
+                    Ok(Instruction::CALL(_)) => {
+                        self.step_over = Some((pc + 2, cpu.stack().len()));
+                    }
+                    _ => self.steps = Some(1),
+                }
+                self.resume();
+                Ok(String::new())
+            }
+            ("c", []) | ("continue", []) => {
+                self.resume();
+                Ok(String::new())
+            }
+            ("p", []) | ("pause", []) => {
+                self.pause();
+                Ok(disassemble(cpu, cpu.program_counter(), 1))
+            }
+            ("b", [addr]) | ("break", [addr]) => {
+                let addr = parse_addr(cpu, addr)?;
+                self.add_breakpoint(Breakpoint::Address(addr));
+                Ok(format!("{}: {}", self.breakpoints.len() - 1, Breakpoint::Address(addr)))
+            }
+            ("bo", [pattern]) | ("breakop", [pattern]) => {
+                let breakpoint = Breakpoint::opcode(pattern)?;
+                self.add_breakpoint(breakpoint);
+                Ok(format!("{}: {}", self.breakpoints.len() - 1, breakpoint))
+            }
+            ("w", [target]) | ("watch", [target]) if target.starts_with(['v', 'V']) => {
+                let index = parse_register(target)?;
+                self.add_watchpoint(Watchpoint::Register(index));
+                Ok(format!("{}: {}", self.breakpoints.len() + self.watchpoints.len() - 1, Watchpoint::Register(index)))
+            }
+            ("w", [start]) | ("watch", [start]) => self.command_watch(cpu, start, start),
+            ("w", [start, end]) | ("watch", [start, end]) => self.command_watch(cpu, start, end),
+            ("l", []) | ("list", []) => Ok(self.list()),
+            ("d", []) | ("delete", []) => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                Ok("Deleted all breakpoints and watchpoints".to_string())
+            }
+            ("d", [index]) | ("delete", [index]) => {
+                let index = parse_count(index)?;
+                if index < self.breakpoints.len() {
+                    self.breakpoints.remove(index);
+                }
+                else if index - self.breakpoints.len() < self.watchpoints.len() {
+                    self.watchpoints.remove(index - self.breakpoints.len());
+                }
+                else {
+                    return Err(Chip8Error::InvalidCommand(format!("No breakpoint or watchpoint {}", index)))
+                }
+                Ok(self.list())
+            }
+            ("r", []) | ("regs", []) => Ok(registers(cpu)),
+            ("x", [addr]) => Ok(hexdump(cpu, parse_addr(cpu, addr)?, HEXDUMP_DEFAULT_LENGTH)),
+            ("x", [addr, length]) => Ok(hexdump(cpu, parse_addr(cpu, addr)?, parse_count(length)?)),
+            ("dis", []) => {
+                let pc = cpu.program_counter();
+                let start = pc.saturating_sub(2 * DISASSEMBLY_CONTEXT);
+                Ok(disassemble(cpu, start, 2 * DISASSEMBLY_CONTEXT + 1))
+            }
+            ("dis", [addr]) => Ok(disassemble(cpu, parse_addr(cpu, addr)?, 2 * DISASSEMBLY_CONTEXT + 1)),
+            ("dis", [addr, count]) => Ok(disassemble(cpu, parse_addr(cpu, addr)?, parse_count(count)?)),
+            ("h", []) | ("help", []) => Ok(HELP.to_string()),
+            _ => Err(Chip8Error::InvalidCommand(format!("Unknown command {:?}, try help", line))),
+        }
+    }
+
+    fn command_watch(&mut self, cpu: &CPU, start: &str, end: &str) -> Result<String, Chip8Error> {
+        let (start, end) = (parse_addr(cpu, start)?, parse_addr(cpu, end)?);
+        if end < start {
+            return Err(Chip8Error::InvalidCommand(format!("{:#05X} is before {:#05X}", end, start)))
+        }
+        let watchpoint = Watchpoint::Memory { start, end };
+        self.add_watchpoint(watchpoint);
+        Ok(format!("{}: {}", self.breakpoints.len() + self.watchpoints.len() - 1, watchpoint))
+    }
+
+    /// Breakpoints then watchpoints, numbered as `delete` expects
+    fn list(&self) -> String {
+        let mut out = String::new();
+        let lines = self.breakpoints.iter().map(|b| b.to_string())
+            .chain(self.watchpoints.iter().map(|w| w.to_string()));
+        for (index, line) in lines.enumerate() {
+            let _ = writeln!(out, "{}: {}", index, line);
+        }
+        if out.is_empty() {
+            out.push_str("No breakpoints or watchpoints");
+        }
+        out.trim_end().to_string()
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+/// V0 to VF, I, PC, SP, the stack and the timers
+pub fn registers(cpu: &CPU) -> String {
+    let mut out = String::new();
+    for index in 0..GP_REGISTERS_COUNT {
+        let _ = write!(out, "V{:X}={:02X}", index, cpu.register(index));
+        out.push(if index % 8 == 7 { '\n' } else { ' ' });
+    }
+    let _ = writeln!(
+        out,
+        "PC={:#05X} I={:#05X} SP={} DT={:02X} ST={:02X}",
+        cpu.program_counter(),
+        cpu.index_register(),
+        cpu.stack().len(),
+        cpu.delay_timer().get().unwrap_or(0),
+        cpu.sound_timer().get().unwrap_or(0)
+    );
+    let stack: Vec<String> = cpu.stack().iter().map(|addr| format!("{:#05X}", addr)).collect();
+    let _ = write!(out, "stack: [{}]", stack.join(", "));
+    out
+}
+
+/// `length` bytes from `start`, 16 per row, stopping at the end of the memory
+pub fn hexdump(cpu: &CPU, start: Addr, length: usize) -> String {
+    let end = start.saturating_add(length).min(cpu.memory_size());
+    let mut out = String::new();
+    for row in (start..end).step_by(HEXDUMP_ROW) {
+        let _ = write!(out, "{:#06X}:", row);
+        for addr in row..(row + HEXDUMP_ROW).min(end) {
+            let _ = write!(out, " {:02X}", cpu.read_memory(addr).unwrap_or(0));
+        }
+        out.push('\n');
+    }
+    out.trim_end().to_string()
+}
+
+/// `count` instructions from `start`, the one at PC marked with `>`
+pub fn disassemble(cpu: &CPU, start: Addr, count: usize) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    for _ in 0..count {
+        if addr + 1 >= cpu.memory_size() {
+            break;
+        }
+        let marker = if addr == cpu.program_counter() { '>' } else { ' ' };
+        let (text, size) = match cpu.instruction_at(addr) {
+            Ok(instr) => (instr.to_string(), instr.size()),
+            Err(_) => ("???".to_string(), 2),
+        };
+        let _ = writeln!(out, "{} {:#05X}: {:04X}  {}", marker, addr, opcode_at(cpu, addr), text);
+        addr += size;
+    }
+    out.trim_end().to_string()
+}
+
+fn opcode_at(cpu: &CPU, addr: Addr) -> u16 {
+    let high = cpu.read_memory(addr).unwrap_or(0) as u16;
+    let low = cpu.read_memory(addr + 1).unwrap_or(0) as u16;
+    (high << 8) | low
+}
+
+/// Hexadecimal address, with or without 0x, inside the memory
+fn parse_addr(cpu: &CPU, text: &str) -> Result<Addr, Chip8Error> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    match Addr::from_str_radix(digits, 16) {
+        Ok(addr) if addr < cpu.memory_size() => Ok(addr),
+        Ok(addr) => Err(Chip8Error::MemoryOutOfRange { addr }),
+        Err(_) => Err(Chip8Error::InvalidCommand(format!("{:?} is not a hexadecimal address", text))),
+    }
+}
+
+fn parse_count(text: &str) -> Result<usize, Chip8Error> {
+    text.parse().map_err(|_| Chip8Error::InvalidCommand(format!("{:?} is not a number", text)))
+}
+
+/// V0 to VF, case insensitive
+fn parse_register(text: &str) -> Result<VIndex, Chip8Error> {
+    match VIndex::from_str_radix(&text[1..], 16) {
+        Ok(index) if index < GP_REGISTERS_COUNT => Ok(index),
+        _ => Err(Chip8Error::InvalidCommand(format!("{:?} is not a register", text))),
+    }
+}
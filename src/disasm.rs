@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::{Addr, Instruction, PROGRAM_START};
+
+/// Bytes per `db` line of data
+const DATA_ROW: usize = 8;
+
+/// ROM split between code reachable from PROGRAM_START and data, with labels on
+/// jump and call targets
+pub struct Disassembly<'a> {
+    rom: &'a [u8],
+    /// Instruction starting at each offset reached, the bytes it covers are code
+    instructions: BTreeMap<Addr, Instruction>,
+    code: Vec<bool>,
+    labels: BTreeMap<Addr, String>,
+}
+
+impl<'a> Disassembly<'a> {
+
+    /// Follows every path from PROGRAM_START. BNNN targets are followed with V0 = 0,
+    /// the other ones can't be known statically and end up as data
+    pub fn new(rom: &'a [u8]) -> Disassembly<'a> {
+        let mut disassembly = Disassembly {
+            rom,
+            instructions: BTreeMap::new(),
+            code: vec![false; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        disassembly.explore();
+        disassembly
+    }
+
+    fn explore(&mut self) {
+        let mut pending = vec![PROGRAM_START];
+        while let Some(addr) = pending.pop() {
+            if self.instructions.contains_key(&addr) {
+                continue;
+            }
+            let instr = match self.decode(addr) {
+                Some(instr) => instr,
+                None => continue,
+            };
+            let next = addr + instr.size();
+            for offset in self.offset(addr)..self.offset(next) {
+                self.code[offset] = true;
+            }
+
+            match instr {
+                Instruction::JP(target) => {
+                    self.label(target, "L");
+                    pending.push(target);
+                }
+                Instruction::JP_V0(target) => {
+                    self.label(target, "L");
+                    pending.push(target);
+                }
+                Instruction::CALL(target) => {
+                    self.label(target, "sub_");
+                    pending.push(target);
+                    pending.push(next);
+                }
+                Instruction::RET | Instruction::EXIT => {}
+                Instruction::SEi(..) | Instruction::SNEi(..) | Instruction::SE(..)
+                | Instruction::SNE(..) | Instruction::SKP(_) | Instruction::SKNP(_) => {
+                    pending.push(next);
+                    if let Some(skipped) = self.decode(next) {
+                        pending.push(next + skipped.size());
+                    }
+                    else {
+                        pending.push(next + 2);
+                    }
+                }
+                _ => pending.push(next),
+            }
+            self.instructions.insert(addr, instr);
+        }
+    }
+
+    /// Instruction stored at addr, None outside of the ROM or for unknown opcodes
+    fn decode(&self, addr: Addr) -> Option<Instruction> {
+        let offset = addr.checked_sub(PROGRAM_START)?;
+        let word = |offset: usize| -> Option<u16> {
+            Some(((*self.rom.get(offset)? as u16) << 8) | *self.rom.get(offset + 1)? as u16)
+        };
+        match Instruction::decode(word(offset)?).ok()? {
+            Instruction::LD_I_LONG(_) => Some(Instruction::LD_I_LONG(word(offset + 2)? as Addr)),
+            instr => Some(instr),
+        }
+    }
+
+    fn offset(&self, addr: Addr) -> usize {
+        (addr - PROGRAM_START).min(self.rom.len())
+    }
+
+    /// Call targets keep their sub_ label when also jumped to. Targets outside of the ROM stay addresses
+    fn label(&mut self, addr: Addr, prefix: &str) {
+        if addr < PROGRAM_START || addr >= PROGRAM_START + self.rom.len() {
+            return;
+        }
+        let name = format!("{}{:03X}", prefix, addr);
+        let label = self.labels.entry(addr).or_insert_with(|| name.clone());
+        if prefix == "sub_" {
+            *label = name;
+        }
+    }
+
+    pub fn is_code(&self, addr: Addr) -> bool {
+        addr.checked_sub(PROGRAM_START).is_some_and(|offset| self.code.get(offset) == Some(&true))
+    }
+
+    pub fn label_at(&self, addr: Addr) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (Addr, &str)> {
+        self.labels.iter().map(|(addr, label)| (*addr, label.as_str()))
+    }
+
+    /// Mnemonic with jump and call targets replaced by their label
+    fn mnemonic(&self, instr: &Instruction) -> String {
+        let target = |addr: &Addr| self.label_at(*addr).map(|label| label.to_string())
+            .unwrap_or_else(|| format!("{:#05X}", addr));
+        match instr {
+            Instruction::JP(addr) => format!("JP {}", target(addr)),
+            Instruction::JP_V0(addr) => format!("JP V0, {}", target(addr)),
+            Instruction::CALL(addr) => format!("CALL {}", target(addr)),
+            instr => instr.to_string(),
+        }
+    }
+
+    /// One line per instruction or row of data, each followed by its address and bytes in a comment
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let addr = PROGRAM_START + offset;
+            if let Some(label) = self.label_at(addr) {
+                let _ = writeln!(out, "{}:", label);
+            }
+
+            if let Some(instr) = self.instructions.get(&addr) {
+                let bytes = &self.rom[offset..offset + instr.size()];
+                let _ = writeln!(out, "    {:<24}; {:#05X}  {}", self.mnemonic(instr), addr, hex(bytes));
+                offset += instr.size();
+                continue;
+            }
+
+            // Data runs until the next instruction or label, DATA_ROW bytes per line at most
+            let mut end = offset + 1;
+            while end < self.rom.len() && end - offset < DATA_ROW
+                && !self.code[end] && self.label_at(PROGRAM_START + end).is_none() {
+                end += 1;
+            }
+            let bytes = &self.rom[offset..end];
+            let values: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+            let _ = writeln!(out, "    {:<24}; {:#05X}  {}", format!("db {}", values.join(", ")), addr, hex(bytes));
+            offset = end;
+        }
+        out
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
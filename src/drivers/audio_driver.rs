@@ -1,8 +1,13 @@
-use sdl2;
 use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
 
+use crate::cpu::{AUDIO_PATTERN_SIZE, DEFAULT_AUDIO_PATTERN};
+
+const PATTERN_BITS: f32 = (AUDIO_PATTERN_SIZE * 8) as f32;
+
 pub struct AudioDriver {
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<PatternWave>,
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    rate: f32,
 }
 
 impl AudioDriver {
@@ -15,21 +20,36 @@ impl AudioDriver {
             samples: None, // default sample size
         };
 
+        let rate = 4000.0;
         let device = audio_subsystem
             .open_playback(None, &desired_spec, |spec| {
                 // Show obtained AudioSpec
                 println!("{:?}", spec);
 
                 // initialize the audio callback
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
+                PatternWave {
+                    pattern: DEFAULT_AUDIO_PATTERN,
+                    phase_inc: rate / spec.freq as f32,
+                    freq: spec.freq as f32,
                     phase: 0.0,
                     volume: 0.25,
                 }
             })
             .unwrap();
 
-        AudioDriver { device: device }
+        AudioDriver { device, pattern: DEFAULT_AUDIO_PATTERN, rate }
+    }
+
+    /// Pattern and playback rate as given by the CPU, the device is only locked on changes
+    pub fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        if self.pattern == *pattern && self.rate == rate {
+            return;
+        }
+        self.pattern = *pattern;
+        self.rate = rate;
+        let mut wave = self.device.lock();
+        wave.pattern = *pattern;
+        wave.phase_inc = rate / wave.freq;
     }
 
     pub fn start_beep(&self) {
@@ -43,20 +63,24 @@ impl AudioDriver {
 
 
 
-struct SquareWave {
+/// Plays the XO-CHIP 128 bits pattern in a loop
+struct PatternWave {
+    pattern: [u8; AUDIO_PATTERN_SIZE],
     phase_inc: f32,
+    freq: f32,
     phase: f32,
     volume: f32,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for PatternWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            let bit = self.phase as usize;
+            let high = self.pattern[bit / 8] >> (7 - bit % 8) & 1 == 1;
+            *x = self.volume * if high { 1.0 } else { -1.0 };
+            self.phase = (self.phase + self.phase_inc) % PATTERN_BITS;
         }
     }
 }
@@ -1,10 +1,9 @@
-use sdl2;
 use sdl2::pixels;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-use crate::cpu::{FRAME_BUFFER_HEIGHT, FRAME_BUFFER_LENGTH};
+use crate::cpu::{FrameBuffer, FRAME_BUFFER_HEIGHT, FRAME_BUFFER_LENGTH};
 
 const SCALE_FACTOR: u32 = 20;
 const SCREEN_WIDTH: u32 = (FRAME_BUFFER_LENGTH as u32) * SCALE_FACTOR;
@@ -19,7 +18,7 @@ impl DisplayDriver {
         let video_subsys = sdl_context.video().unwrap();
         let window = video_subsys
             .window(
-                "rust-sdl2_gfx: draw line & FPSManager",
+                "chip-8 emulator",
                 SCREEN_WIDTH,
                 SCREEN_HEIGHT,
             )
@@ -34,28 +33,36 @@ impl DisplayDriver {
         canvas.clear();
         canvas.present();
 
-        DisplayDriver { canvas: canvas }
+        DisplayDriver { canvas }
     }
 
-    pub fn draw(&mut self, pixels: &[[u8; FRAME_BUFFER_LENGTH]; FRAME_BUFFER_HEIGHT]) {
-        for (y, row) in pixels.iter().enumerate() {
+    pub fn set_title(&mut self, title: &str) {
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    pub fn draw(&mut self, pixels: &FrameBuffer) {
+        // The window keeps its size, high resolution pixels are just smaller
+        let scale = SCREEN_WIDTH / pixels.width() as u32;
+        for (y, row) in pixels.rows().enumerate() {
             for (x, &col) in row.iter().enumerate() {
-                let x = (x as u32) * SCALE_FACTOR;
-                let y = (y as u32) * SCALE_FACTOR;
+                let x = (x as u32) * scale;
+                let y = (y as u32) * scale;
 
                 self.canvas.set_draw_color(color(col));
                 let _ = self.canvas
-                    .fill_rect(Rect::new(x as i32, y as i32, SCALE_FACTOR, SCALE_FACTOR));
+                    .fill_rect(Rect::new(x as i32, y as i32, scale, scale));
             }
         }
         self.canvas.present();
     }
 }
 
+/// One color per combination of the two XO-CHIP planes
 fn color(value: u8) -> pixels::Color {
-    if value == 0 {
-        pixels::Color::RGB(0, 0, 0)
-    } else {
-        pixels::Color::RGB(250,250,250)
+    match value {
+        0 => pixels::Color::RGB(0, 0, 0),
+        1 => pixels::Color::RGB(250,250,250),
+        2 => pixels::Color::RGB(170, 170, 170),
+        _ => pixels::Color::RGB(85, 85, 85),
     }
 }
\ No newline at end of file
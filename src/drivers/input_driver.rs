@@ -1,23 +1,63 @@
-use sdl2;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
+use crate::cpu::KEY_COUNT;
+use crate::error::Chip8Error;
+use crate::keymap::KeyMap;
+
+/// Emulator commands bound to keys outside of the CHIP-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    /// F5
+    SaveState,
+    /// F9
+    LoadState,
+    /// F6
+    PreviousSlot,
+    /// F7
+    NextSlot,
+}
+
+/// Held down to rewind
+const REWIND_KEY: Keycode = Keycode::Backspace;
+
+fn hotkey(key: Keycode) -> Option<Hotkey> {
+    match key {
+        Keycode::F5 => Some(Hotkey::SaveState),
+        Keycode::F9 => Some(Hotkey::LoadState),
+        Keycode::F6 => Some(Hotkey::PreviousSlot),
+        Keycode::F7 => Some(Hotkey::NextSlot),
+        _ => None,
+    }
+}
+
 pub struct InputDriver {
     events: sdl2::EventPump,
+    hotkeys: Vec<Hotkey>,
+    rewind: bool,
+    /// Host key and the CHIP-8 key it presses
+    bindings: Vec<(Keycode, usize)>,
 }
 
 impl InputDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        InputDriver { events: sdl_context.event_pump().unwrap() }
+    /// Fails on key names SDL does not know
+    pub fn new(sdl_context: &sdl2::Sdl, keymap: &KeyMap) -> Result<Self, Chip8Error> {
+        let bindings = keycodes(keymap)?;
+        Ok(InputDriver { events: sdl_context.event_pump().unwrap(), hotkeys: Vec::new(), rewind: false, bindings })
     }
 
 
-    pub fn poll(&mut self) -> Result<[bool; 16], ()> {
+    #[allow(clippy::result_unit_err)]
+    pub fn poll(&mut self) -> Result<[bool; KEY_COUNT], ()> {
 
         for event in self.events.poll_iter() {
-            if let Event::Quit { .. } = event {
-                return Err(());
-            };
+            match event {
+                Event::Quit { .. } => return Err(()),
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    self.hotkeys.extend(hotkey(key));
+                }
+                _ => {}
+            }
         }
 
         let keys: Vec<Keycode> = self.events
@@ -26,34 +66,42 @@ impl InputDriver {
             .filter_map(Keycode::from_scancode)
             .collect();
 
-        let mut chip8_keys = [false; 16];
-
-        for key in keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xc),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xd),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xe),
-                Keycode::Z => Some(0xa),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xb),
-                Keycode::V => Some(0xf),
-                _ => None,
-            };
-
-            if let Some(i) = index {
-                chip8_keys[i] = true;
+        let mut chip8_keys = [false; KEY_COUNT];
+        self.rewind = keys.contains(&REWIND_KEY);
+
+        for (keycode, index) in self.bindings.iter() {
+            if keys.contains(keycode) {
+                chip8_keys[*index] = true;
             }
         }
 
         Ok(chip8_keys)
     }
+
+    /// True while Backspace is held, as of the last poll
+    pub fn rewind_held(&self) -> bool {
+        self.rewind
+    }
+
+    /// Hotkeys pressed since the previous call, in order
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+}
+
+/// SDL keys of `keymap`, each with the CHIP-8 key it presses. Keys of the hotkeys
+/// and rewind can't be bound
+pub fn keycodes(keymap: &KeyMap) -> Result<Vec<(Keycode, usize)>, Chip8Error> {
+    let mut keycodes = Vec::new();
+    for key in 0..KEY_COUNT {
+        for name in keymap.bindings(key as u8) {
+            let keycode = Keycode::from_name(name)
+                .ok_or_else(|| Chip8Error::InvalidKeyMap(format!("unknown key name {:?} for key {:X}", name, key)))?;
+            if keycode == REWIND_KEY || hotkey(keycode).is_some() {
+                return Err(Chip8Error::InvalidKeyMap(format!("{:?} bound to key {:X} is a hotkey", name, key)))
+            }
+            keycodes.push((keycode, key));
+        }
+    }
+    Ok(keycodes)
 }
@@ -1,9 +1,7 @@
 mod display_driver;
 mod audio_driver;
 mod input_driver;
-mod cartridge_driver;
 
 pub use self::display_driver::DisplayDriver;
 pub use self::audio_driver::AudioDriver;
-pub use self::input_driver::InputDriver;
-pub use self::cartridge_driver::CartridgeDriver;
+pub use self::input_driver::{InputDriver, Hotkey, keycodes};
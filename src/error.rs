@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::cpu::{Addr, VIndex};
+
+/// Everything that can go wrong while loading or running a program
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// CALL with the STACK_SIZE return addresses already in use
+    StackOverflow,
+    /// RET with no return address on the stack
+    StackUnderflow,
+    /// Opcode that does not decode to any Instruction, or is not supported
+    IllegalOpcode { opcode: u16, pc: Addr },
+    /// Opcode that does not decode to any Instruction, found outside of the CPU
+    UnknownOpcode { opcode: u16 },
+    /// Read or write past the end of the memory
+    MemoryOutOfRange { addr: Addr },
+    /// Program counter moved past the end of the memory
+    ProgramCounterOutOfRange { addr: Addr },
+    RegisterOutOfRange { index: VIndex },
+    PixelOutOfRange { row: usize, column: usize },
+    InvalidKey { key: u8 },
+    /// Fonts must fit between `addr` and PROGRAM_START
+    InvalidFontAddress { addr: Addr },
+    /// ROM of `size` bytes when only `max` fit after PROGRAM_START
+    RomTooLarge { size: usize, max: usize },
+    /// Line of a text program that is not a 16 bits hexadecimal opcode, counted from 1
+    InvalidHexLine { line: usize, content: String },
+    UnknownQuirks(String),
+    /// Save state written by a newer version of the format
+    UnsupportedSaveStateVersion { version: u8 },
+    /// Data that is not a save state, or describes an impossible machine
+    InvalidSaveState(String),
+    /// Assembly source error, line and column counted from 1
+    Assembly { file: String, line: usize, column: usize, message: String },
+    /// Debugger command or scripted key press that can't be parsed, with the reason
+    InvalidCommand(String),
+    /// Key bindings that can't be read, with the reason
+    InvalidKeyMap(String),
+    /// Screenshot format other than ascii, pbm or png
+    UnknownFormat(String),
+    /// Data that is not a movie, or a movie that can't be played on this machine
+    InvalidMovie(String),
+    /// Movie recorded with another program, hashes as given by movie::rom_hash
+    RomMismatch { expected: u64, found: u64 },
+    /// Replayed screen differs from the recording at the end of `frame`, counted from 0
+    Desync { frame: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::StackOverflow => write!(f, "Stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "Stack is empty, can't return"),
+            Chip8Error::IllegalOpcode { opcode, pc } => {
+                write!(f, "Illegal opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::UnknownOpcode { opcode } => write!(f, "Unknown opcode {:#06X}", opcode),
+            Chip8Error::MemoryOutOfRange { addr } => {
+                write!(f, "Memory access out of range at {:#05X}", addr)
+            }
+            Chip8Error::ProgramCounterOutOfRange { addr } => {
+                write!(f, "Program counter out of the memory at {:#05X}", addr)
+            }
+            Chip8Error::RegisterOutOfRange { index } => {
+                write!(f, "Register V{:X} does not exist", index)
+            }
+            Chip8Error::PixelOutOfRange { row, column } => {
+                write!(f, "Pixel ({}, {}) is out of the screen", row, column)
+            }
+            Chip8Error::InvalidKey { key } => {
+                write!(f, "Key {:#X} does not exist, keypad goes from 0x0 to 0xF", key)
+            }
+            Chip8Error::InvalidFontAddress { addr } => {
+                write!(f, "Font can't be stored at {:#05X}, it must fit below the program", addr)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes long, at most {} bytes fit in memory", size, max)
+            }
+            Chip8Error::InvalidHexLine { line, content } => {
+                write!(f, "Line {}: {:?} is not a hexadecimal opcode", line, content)
+            }
+            Chip8Error::UnknownQuirks(name) => {
+                write!(f, "Unknown quirks preset {}, use chip8, chip48, schip or xochip", name)
+            }
+            Chip8Error::UnsupportedSaveStateVersion { version } => {
+                write!(f, "Save state version {} is not supported", version)
+            }
+            Chip8Error::InvalidSaveState(reason) => write!(f, "Invalid save state: {}", reason),
+            Chip8Error::Assembly { file, line, column, message } => {
+                write!(f, "{}:{}:{}: {}", file, line, column, message)
+            }
+            Chip8Error::InvalidCommand(reason) => write!(f, "{}", reason),
+            Chip8Error::InvalidKeyMap(reason) => write!(f, "Invalid key bindings: {}", reason),
+            Chip8Error::UnknownFormat(name) => {
+                write!(f, "Unknown image format {}, use ascii, pbm or png", name)
+            }
+            Chip8Error::InvalidMovie(reason) => write!(f, "Invalid movie: {}", reason),
+            Chip8Error::RomMismatch { expected, found } => {
+                write!(f, "Movie was recorded with another program, hash {:016X} instead of {:016X}", expected, found)
+            }
+            Chip8Error::Desync { frame } => {
+                write!(f, "Replay went out of sync with the recording at frame {}", frame)
+            }
+            Chip8Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Chip8Error {
+        Chip8Error::Io(e)
+    }
+}
@@ -0,0 +1,147 @@
+use std::str::FromStr;
+
+use crate::cpu::{CPU, Addr, Instruction, KEY_COUNT};
+use crate::debugger::{Breakpoint, Debugger, StopReason};
+use crate::error::Chip8Error;
+use crate::movie::Movie;
+
+/// Frames a scripted key stays down when the script does not say
+pub const DEFAULT_PRESS_FRAMES: u64 = 6;
+
+/// Key held down from the start of `frame` for `frames` frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub frames: u64,
+}
+
+impl KeyPress {
+
+    fn is_down(&self, frame: u64) -> bool {
+        frame >= self.frame && frame - self.frame < self.frames
+    }
+}
+
+/// `FRAME:KEY[:FRAMES]`, the frame and duration in decimal, the key a hexadecimal digit
+impl FromStr for KeyPress {
+    type Err = Chip8Error;
+
+    fn from_str(script: &str) -> Result<KeyPress, Chip8Error> {
+        let invalid = || Chip8Error::InvalidCommand(format!("{:?} is not a FRAME:KEY[:FRAMES] key press", script));
+        let fields: Vec<&str> = script.split(':').collect();
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(invalid())
+        }
+        let frame = fields[0].parse().map_err(|_| invalid())?;
+        let key = u8::from_str_radix(fields[1], 16).map_err(|_| invalid())?;
+        if key as usize >= KEY_COUNT {
+            return Err(Chip8Error::InvalidKey { key })
+        }
+        let frames = match fields.get(2) {
+            Some(frames) => frames.parse().map_err(|_| invalid())?,
+            None => DEFAULT_PRESS_FRAMES,
+        };
+        Ok(KeyPress { frame, key, frames })
+    }
+}
+
+/// When a headless run may stop before its last frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// PC reaches the address, before the instruction there is executed
+    Pc(Addr),
+    /// Program jumps to itself or exits, the usual ways for test ROMs to end
+    Loop,
+}
+
+/// How a headless run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Stop condition met during frame `frame`, counted from 0
+    Reached { frame: u64 },
+    /// Program exited with 00FD during frame `frame`
+    Exited { frame: u64 },
+    /// Every frame was run
+    Timeout,
+}
+
+/// Runs a CPU frame after frame without any host, pressing keys on a script
+pub struct Headless {
+    frames: u64,
+    until: Option<Until>,
+    presses: Vec<KeyPress>,
+    movie: Option<Movie>,
+}
+
+impl Headless {
+
+    /// Runs at most `frames` 60th of a second
+    pub fn new(frames: u64) -> Headless {
+        Headless {
+            frames,
+            until: None,
+            presses: Vec::new(),
+            movie: None,
+        }
+    }
+
+    pub fn set_until(&mut self, until: Until) {
+        self.until = Some(until);
+    }
+
+    pub fn add_key_press(&mut self, press: KeyPress) {
+        self.presses.push(press);
+    }
+
+    /// Plays the keypad of `movie` instead of the key presses, checking the screen
+    /// against it. See Movie::start_replay to set up the CPU
+    pub fn set_movie(&mut self, movie: Movie) {
+        self.movie = Some(movie);
+    }
+
+    /// Keypad state during `frame`, no key being down once the movie is over
+    pub fn keys(&self, frame: u64) -> [bool; KEY_COUNT] {
+        if let Some(movie) = self.movie.as_ref() {
+            return movie.keys(frame as usize).unwrap_or([false; KEY_COUNT])
+        }
+        let mut keys = [false; KEY_COUNT];
+        for press in self.presses.iter().filter(|press| press.is_down(frame)) {
+            keys[press.key as usize] = true;
+        }
+        keys
+    }
+
+    /// Runs the powered on `cpu` until the stop condition, an exit or the last frame
+    pub fn run(&self, cpu: &mut CPU) -> Result<Outcome, Chip8Error> {
+        let mut debugger = Debugger::new();
+        if let Some(Until::Pc(addr)) = self.until {
+            debugger.add_breakpoint(Breakpoint::Address(addr));
+        }
+        debugger.resume();
+
+        for frame in 0..self.frames {
+            cpu.set_keys(&self.keys(frame));
+            let cycles = cpu.cycles_per_frame();
+            if let Some(StopReason::Breakpoint { .. }) = debugger.run_frame(cpu, cycles)? {
+                return Ok(Outcome::Reached { frame })
+            }
+            if let Some(movie) = self.movie.as_ref() {
+                movie.verify_frame(frame as usize, cpu)?;
+            }
+            if !cpu.is_on() {
+                return Ok(Outcome::Exited { frame })
+            }
+            if self.until == Some(Until::Loop) && is_looping(cpu) {
+                return Ok(Outcome::Reached { frame })
+            }
+        }
+        Ok(Outcome::Timeout)
+    }
+}
+
+/// PC is on a jump to itself
+fn is_looping(cpu: &CPU) -> bool {
+    let pc = cpu.program_counter();
+    matches!(cpu.instruction_at(pc), Ok(Instruction::JP(target)) if target == pc)
+}
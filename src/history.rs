@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+use crate::cpu::{CPU, Snapshot};
+
+/// Bounded ring buffer of snapshots, the oldest one being dropped when full.
+/// Snapshots share the memory pages nobody wrote to, so each one costs the screen,
+/// the registers and the pages its frame changed, even with 64 KiB of XO-CHIP memory
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize
+}
+
+impl History {
+
+    /// Keeps at most `capacity` snapshots, e.g. 30 * 60 for 30 seconds at one per frame
+    pub fn new(capacity: usize) -> History {
+        History {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Records the current state of the CPU
+    pub fn push(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(cpu.snapshot());
+    }
+
+    /// Removes and returns the most recent snapshot
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        self.snapshots.pop_back()
+    }
+
+    /// Restores the most recent snapshot and forgets it, false when there is nothing left to rewind
+    pub fn rewind(&mut self, cpu: &mut CPU) -> bool {
+        match self.pop() {
+            Some(snapshot) => {
+                cpu.restore(&snapshot);
+                true
+            }
+            None => false
+        }
+    }
+}
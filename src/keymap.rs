@@ -0,0 +1,132 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::cpu::KEY_COUNT;
+use crate::error::Chip8Error;
+
+/// Host keys of the usual layout, the left side of a QWERTY keyboard, by CHIP-8 key
+const QWERTY: [&str; KEY_COUNT] = [
+    "X", "1", "2", "3",
+    "Q", "W", "E", "A",
+    "S", "D", "Z", "C",
+    "4", "R", "F", "V",
+];
+
+/// Host keys bound to each CHIP-8 key, by SDL key name ("Q", "Keypad 4", "Up").
+///
+/// Read from TOML, CHIP-8 keys being hexadecimal digits, bound to one key name or
+/// a list of them. `[keys]` applies to every program, `[rom."<file name>"]` to one
+/// program only, and each replaces the bindings of the keys it lists:
+/// ```toml
+/// [keys]
+/// 4 = "A"
+/// 5 = ["Z", "Up"]
+///
+/// [rom."pong.ch8"]
+/// 1 = ["Z", "Up"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: [Vec<String>; KEY_COUNT],
+}
+
+impl KeyMap {
+
+    pub fn qwerty() -> KeyMap {
+        KeyMap { bindings: QWERTY.map(|name| vec![name.to_string()]) }
+    }
+
+    /// QWERTY layout with the bindings of `config`, then those of the program
+    /// named `rom` when it has some
+    pub fn from_toml(config: &str, rom: Option<&str>) -> Result<KeyMap, Chip8Error> {
+        let config: Table = config.parse().map_err(|e: toml::de::Error| Chip8Error::InvalidKeyMap(e.message().to_string()))?;
+        let mut keymap = KeyMap::qwerty();
+        for (name, value) in config.iter() {
+            match (name.as_str(), value) {
+                ("keys", Value::Table(keys)) => keymap.bind_table(keys)?,
+                // Every program is checked, not only the one running
+                ("rom", Value::Table(roms)) => for (rom, keys) in roms.iter() {
+                    let keys = keys.as_table()
+                        .ok_or_else(|| Chip8Error::InvalidKeyMap(format!("rom {:?} must be a table of keys", rom)))?;
+                    KeyMap::qwerty().bind_table(keys)?;
+                }
+                _ => return Err(Chip8Error::InvalidKeyMap(format!("unexpected {:?}, use [keys] or [rom.\"<file name>\"]", name))),
+            }
+        }
+        let overrides = rom.and_then(|rom| config.get("rom")?.get(rom)?.as_table());
+        if let Some(keys) = overrides {
+            keymap.bind_table(keys)?;
+        }
+        Ok(keymap)
+    }
+
+    /// Reads `path`, keeping the bindings of the ROM at `rom`
+    pub fn load(path: impl AsRef<Path>, rom: Option<impl AsRef<Path>>) -> Result<KeyMap, Chip8Error> {
+        let config = fs::read_to_string(path)?;
+        let name = rom.as_ref().and_then(|rom| rom.as_ref().file_name()).map(|name| name.to_string_lossy());
+        KeyMap::from_toml(&config, name.as_deref())
+    }
+
+    /// `$XDG_CONFIG_HOME/chip8/keymap.toml`, `~/.config/chip8/keymap.toml` without it
+    pub fn default_path() -> Option<PathBuf> {
+        let config = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("chip8").join("keymap.toml"))
+    }
+
+    /// Host keys bound to CHIP-8 `key`, none when it can't be pressed
+    pub fn bindings(&self, key: u8) -> &[String] {
+        self.bindings.get(key as usize).map_or(&[], Vec::as_slice)
+    }
+
+    /// Replaces the host keys bound to CHIP-8 `key`
+    pub fn bind(&mut self, key: u8, names: Vec<String>) -> Result<(), Chip8Error> {
+        let bindings = self.bindings.get_mut(key as usize).ok_or(Chip8Error::InvalidKey { key })?;
+        *bindings = names;
+        Ok(())
+    }
+
+    fn bind_table(&mut self, keys: &Table) -> Result<(), Chip8Error> {
+        for (key, value) in keys.iter() {
+            let index = match u8::from_str_radix(key, 16) {
+                Ok(index) if key.len() == 1 => index,
+                _ => return Err(Chip8Error::InvalidKeyMap(format!("{:?} is not a CHIP-8 key, use 0 to F", key))),
+            };
+            let invalid = || Chip8Error::InvalidKeyMap(format!("key {} must be bound to a key name or a list of them", key));
+            let names = match value {
+                Value::String(name) => vec![name.clone()],
+                Value::Array(names) => names.iter()
+                    .map(|name| name.as_str().map(str::to_string).ok_or_else(invalid))
+                    .collect::<Result<_, _>>()?,
+                _ => return Err(invalid()),
+            };
+            if names.iter().any(String::is_empty) {
+                return Err(invalid())
+            }
+            self.bind(index, names)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::qwerty()
+    }
+}
+
+/// `[keys]` table of every CHIP-8 key, which from_toml reads back
+impl fmt::Display for KeyMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[keys]")?;
+        for (key, names) in self.bindings.iter().enumerate() {
+            let names: Vec<Value> = names.iter().cloned().map(Value::String).collect();
+            writeln!(f, "{:X} = {}", key, Value::Array(names))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! CHIP-8 emulator core
+//!
+//! The core is headless: it holds the machine state and executes
+//! instructions, but knows nothing about windows, sound cards or keyboards.
+//! The SDL2 frontend lives in [`drivers`] and is only built with the `sdl`
+//! feature.
+
+pub mod asm;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod headless;
+pub mod history;
+pub mod keymap;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+pub mod screenshot;
+pub mod trace;
+
+#[cfg(feature = "sdl")]
+pub mod drivers;
+
+#[cfg(test)]
+mod cpu_test;
+
+pub use cpu::{
+    CPU, Snapshot, Instruction, FrameBuffer, DelayTimer, SoundTimer,
+    Addr, VIndex, VValue, CellValue,
+    MEMORY_SIZE, EXTENDED_MEMORY_SIZE, GP_REGISTERS_COUNT, STACK_SIZE, PROGRAM_START,
+    FRAME_BUFFER_LENGTH, FRAME_BUFFER_HEIGHT, HIRES_FRAME_BUFFER_LENGTH, HIRES_FRAME_BUFFER_HEIGHT,
+    DEFAULT_FREQUENCY, KEY_COUNT, VF, RPL_FLAGS_COUNT,
+    DEFAULT_FONT_ADDRESS, FONT_GLYPH_SIZE, FONT_SET, BIG_FONT_GLYPH_SIZE, BIG_FONT_SET,
+    PLANES_MASK, AUDIO_PATTERN_SIZE, DEFAULT_PITCH, DEFAULT_AUDIO_PATTERN,
+    SAVE_STATE_MAGIC, SAVE_STATE_VERSION,
+};
+pub use debugger::Debugger;
+pub use disasm::Disassembly;
+pub use error::Chip8Error;
+pub use headless::Headless;
+pub use history::History;
+pub use keymap::KeyMap;
+pub use movie::Movie;
+pub use quirks::Quirks;
+pub use random::{RandomSource, SplitMix64};
+pub use trace::TraceEntry;
@@ -1,11 +1,90 @@
 use clap::{Arg, App};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::PathBuf;
 use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-mod cpu;
-use cpu::{CPU, Instruction, FRAME_BUFFER_LENGTH, FRAME_BUFFER_HEIGHT};
+use chip8::{CPU, Chip8Error, Debugger, History, KeyMap, Movie, Quirks};
+use chip8::debugger::disassemble;
+use chip8::drivers::{DisplayDriver, AudioDriver, InputDriver, Hotkey, keycodes};
 
-mod drivers;
-use drivers::{DisplayDriver, AudioDriver};
+/// Save state slots, F6 and F7 cycle through them
+const SAVE_SLOTS: u8 = 10;
+
+/// One snapshot per frame for the last 30 seconds
+const REWIND_FRAMES: usize = 30 * 60;
+
+/// Slot files live next to the ROM
+fn slot_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+fn save_slot(cpu: &CPU, path: &str) -> Result<(), Chip8Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    cpu.save_state(&mut writer)?;
+    Ok(writer.flush()?)
+}
+
+/// Reads debugger commands on another thread, so the window keeps being refreshed while paused
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+fn load_slot(cpu: &mut CPU, path: &str) -> Result<(), Chip8Error> {
+    cpu.load_state(File::open(path)?)
+}
+
+/// Movie recorded to a file, or played back
+enum MovieMode {
+    Record(Movie, String),
+    Replay(Movie),
+}
+
+/// Writes the movie being recorded, if any
+fn save_movie(mode: &Option<MovieMode>) {
+    if let Some(MovieMode::Record(movie, path)) = mode {
+        let written = File::create(path).map_err(Chip8Error::from).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            movie.write(&mut writer)?;
+            Ok(writer.flush()?)
+        });
+        match written {
+            Ok(_) => println!("Movie of {} frames written to {}", movie.len(), path),
+            Err(e) => println!("Can't write {}: {}", path, e),
+        }
+    }
+}
+
+/// Bindings of `keymap`, or of the default file when it exists, with where they come from
+fn load_keymap(keymap: Option<&str>, rom: &str) -> Result<(KeyMap, String), Chip8Error> {
+    let path = match keymap {
+        Some(path) => PathBuf::from(path),
+        None => match KeyMap::default_path().filter(|path| path.exists()) {
+            Some(path) => path,
+            None => return Ok((KeyMap::qwerty(), "default QWERTY layout".to_string())),
+        },
+    };
+    let keymap = KeyMap::load(&path, Some(rom))?;
+    Ok((keymap, path.display().to_string()))
+}
 
 fn main() {
 
@@ -18,6 +97,7 @@ fn main() {
                  .long("file")
                  .takes_value(true)
                  .value_name("FILE")
+                 .required(true)
                  .help("program to be executed"))
         .arg(Arg::new("text input")
                   .short('t')
@@ -31,6 +111,63 @@ fn main() {
                   .takes_value(false)
                   .help("input file as raw")
         )
+        .arg(Arg::new("asm input")
+                  .short('a')
+                  .long("asm")
+                  .takes_value(false)
+                  .help("input file as assembly source")
+        )
+        .arg(Arg::new("quirks")
+                  .short('q')
+                  .long("quirks")
+                  .takes_value(true)
+                  .value_name("PRESET")
+                  .possible_values(Quirks::preset_names())
+                  .default_value("chip8")
+                  .help("interpreter behaviour to emulate")
+        )
+        .arg(Arg::new("seed")
+                  .long("seed")
+                  .takes_value(true)
+                  .value_name("SEED")
+                  .help("seed of the random numbers, for reproducible runs")
+        )
+        .arg(Arg::new("trace")
+                  .long("trace")
+                  .takes_value(true)
+                  .value_name("FILE")
+                  .help("write every executed instruction and the registers to FILE")
+        )
+        .arg(Arg::new("record")
+                  .long("record")
+                  .takes_value(true)
+                  .value_name("MOVIE")
+                  .conflicts_with_all(&["replay", "debug"])
+                  .help("record the keypad of every frame to MOVIE, written on exit")
+        )
+        .arg(Arg::new("replay")
+                  .long("replay")
+                  .takes_value(true)
+                  .value_name("MOVIE")
+                  .conflicts_with_all(&["debug", "seed"])
+                  .help("play the keypad recorded in MOVIE, with its quirks and seed, then hand over to the keyboard")
+        )
+        .arg(Arg::new("keymap")
+                  .long("keymap")
+                  .takes_value(true)
+                  .value_name("FILE")
+                  .help("read key bindings from FILE instead of ~/.config/chip8/keymap.toml")
+        )
+        .arg(Arg::new("print keymap")
+                  .long("print-keymap")
+                  .takes_value(false)
+                  .help("print the key bindings used with the program and exit")
+        )
+        .arg(Arg::new("debug")
+                  .long("debug")
+                  .takes_value(false)
+                  .help("start paused in the command-line debugger")
+        )
         .get_matches();
     
 
@@ -39,66 +176,218 @@ fn main() {
     let filename = matches.value_of("file").unwrap();
     let bin = matches.is_present("raw input");
     let text = matches.is_present("text input");
+    let asm = matches.is_present("asm input");
+    let quirks: Quirks = matches.value_of_t("quirks").unwrap_or_else(|e| e.exit());
+    let debug = matches.is_present("debug");
+    let seed: Option<u64> = matches.is_present("seed").then(|| matches.value_of_t("seed").unwrap_or_else(|e| e.exit()));
+
+    let keymap = load_keymap(matches.value_of("keymap"), filename)
+        .and_then(|(keymap, source)| keycodes(&keymap).map(|_| (keymap, source)));
+    let keymap = match keymap {
+        Ok((keymap, source)) if matches.is_present("print keymap") => {
+            println!("# {}", source);
+            print!("{}", keymap);
+            process::exit(0);
+        }
+        Ok((keymap, _)) => keymap,
+        Err(e) => {
+            println!("Can't set up the keyboard: {}", e);
+            process::exit(-1);
+        }
+    };
 
     // Set up drivers
     let sdl_context = sdl2::init().unwrap();
-    let audio_driver = AudioDriver::new(&sdl_context);
+    let mut audio_driver = AudioDriver::new(&sdl_context);
     let mut display_driver = DisplayDriver::new(&sdl_context);
+    let mut input_driver = InputDriver::new(&sdl_context, &keymap).unwrap();
     
-    if bin && text {
-        println!("Can't handle both bianry and text file. Use -r OR -t");
-        process::exit(-1);
+    match [bin, text, asm].iter().filter(|flag| **flag).count() {
+        0 => {
+            println!("Please select an input file type: either -r, -t or -a");
+            process::exit(-1);
+        }
+        1 => {}
+        _ => {
+            println!("Can't handle several input file types. Use -r, -t OR -a");
+            process::exit(-1);
+        }
     }
-    if !bin && !text {
-        println!("Please select an input file type: either -r or -t");
+
+    let replay = matches.value_of("replay").map(|path| {
+        match File::open(path).map_err(Chip8Error::from).and_then(Movie::read) {
+            Ok(movie) => movie,
+            Err(e) => {
+                println!("Can't read {}: {}", path, e);
+                process::exit(-1);
+            }
+        }
+    });
+    let mut cpu = match replay.as_ref() {
+        Some(movie) => CPU::new(Some(movie.frequency()), movie.quirks()),
+        None => CPU::new(None, quirks),
+    };
+
+    let loaded = if bin { cpu.loadb(filename) } else if asm { cpu.loada(filename) } else { cpu.loadt(filename) };
+    if let Err(e) = loaded {
+        println!("Can't load {}: {}", filename, e);
         process::exit(-1);
     }
 
-    let mut cpu = CPU::new(Some(600));
-
-    if bin {
-        match cpu.loadb(filename){
-            Ok(_) => {},
+    cpu.power_on();
+    if let Some(seed) = seed {
+        cpu.set_seed(seed);
+    }
+    let mut movie = match (replay, matches.value_of("record")) {
+        (Some(movie), _) => {
+            if let Err(e) = movie.start_replay(&mut cpu) {
+                println!("Can't replay: {}", e);
+                process::exit(-1);
+            }
+            Some(MovieMode::Replay(movie))
+        }
+        (None, Some(path)) => Some(MovieMode::Record(Movie::record(&cpu).unwrap(), path.to_string())),
+        (None, None) => None,
+    };
+    let mut frame = 0;
+    if let Some(trace) = matches.value_of("trace") {
+        match File::create(trace) {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                cpu.set_trace_hook(move |entry| { let _ = writeln!(writer, "{}", entry); });
+            }
             Err(e) => {
-                panic!("{}", e);
+                println!("Can't create {}: {}", trace, e);
+                process::exit(-1);
             }
         }
     }
+    let mut slot = 0;
+    let mut title = String::new();
+    let mut history = History::new(REWIND_FRAMES);
+    let mut debugger = if debug {
+        println!("Debugger started paused, type help for the list of commands");
+        println!("{}", disassemble(&cpu, cpu.program_counter(), 1));
+        prompt();
+        Some((Debugger::new(), spawn_command_reader()))
+    }
     else {
-        match cpu.loadt(filename){
-            Ok(_) => {},
-            Err(e) => {
-                panic!("{}", e);
+        None
+    };
+    let frame_duration = Duration::from_secs_f64(1.0 / 60.0);
+    let mut next_frame = Instant::now();
+    while let Ok(keys) = input_driver.poll() {
+        if !cpu.is_on() {
+            break;
+        }
+        let keys = match movie.as_ref() {
+            Some(MovieMode::Replay(replay)) => {
+                if frame == replay.len() {
+                    println!("Replay finished, the keyboard is back");
+                }
+                replay.keys(frame).unwrap_or(keys)
+            }
+            _ => keys,
+        };
+        cpu.set_keys(&keys);
+
+        for hotkey in input_driver.take_hotkeys() {
+            let path = slot_path(filename, slot);
+            let result = match hotkey {
+                Hotkey::SaveState => save_slot(&cpu, &path),
+                // The movie would no longer match the frames played
+                Hotkey::LoadState if movie.is_some() => {
+                    println!("Slot {}: states can't be loaded while a movie records or plays", slot);
+                    Ok(())
+                }
+                Hotkey::LoadState => load_slot(&mut cpu, &path).map(|_| display_driver.draw(cpu.frame_buffer())),
+                Hotkey::PreviousSlot => { slot = (slot + SAVE_SLOTS - 1) % SAVE_SLOTS; Ok(()) }
+                Hotkey::NextSlot => { slot = (slot + 1) % SAVE_SLOTS; Ok(()) }
+            };
+            if let Err(e) = result {
+                println!("Slot {}: {}", slot, e);
             }
         }
-    }
 
-    cpu.power_on();
-    loop {
-        match cpu.next_cycle() {
-            Ok(_) => {},
-            Err(e) => {
-                println!("{}", e);
-                process::exit(-1);
+        if input_driver.rewind_held() && movie.is_none() {
+            // Play stays paused on the oldest frame once the history is exhausted
+            if history.rewind(&mut cpu) {
+                display_driver.draw(cpu.frame_buffer());
             }
         }
-        match cpu.simulate() {
-            Ok(_) => {},
-            Err(e) => {
+        else if let Some((debugger, commands)) = debugger.as_mut() {
+            for line in commands.try_iter() {
+                if matches!(line.trim(), "q" | "quit") {
+                    // Dropping the hook flushes the trace
+                    cpu.remove_trace_hook();
+                    process::exit(0);
+                }
+                match debugger.command(&mut cpu, &line) {
+                    Ok(output) if output.is_empty() => {},
+                    Ok(output) => println!("{}", output),
+                    Err(e) => println!("{}", e),
+                }
+                if debugger.is_paused() {
+                    prompt();
+                }
+            }
+
+            if !debugger.is_paused() {
+                history.push(&cpu);
+            }
+            let cycles = cpu.cycles_per_frame();
+            match debugger.run_frame(&mut cpu, cycles) {
+                Ok(None) => {},
+                Ok(Some(reason)) => {
+                    println!("{}", reason);
+                    println!("{}", disassemble(&cpu, cpu.program_counter(), 1));
+                    prompt();
+                }
+                Err(e) => {
+                    // The debugger paused on the error, the machine can still be inspected
+                    println!("{}", e);
+                    println!("{}", disassemble(&cpu, cpu.program_counter(), 1));
+                    prompt();
+                }
+            }
+        }
+        else {
+            history.push(&cpu);
+            let ran = cpu.run_frame(cpu.cycles_per_frame());
+            let checked = match movie.as_mut() {
+                Some(MovieMode::Record(recording, _)) => { recording.push_frame(&keys, &cpu); Ok(()) }
+                Some(MovieMode::Replay(replay)) => replay.verify_frame(frame, &cpu),
+                None => Ok(()),
+            };
+            frame += 1;
+            if let Err(e) = ran.and(checked) {
                 println!("{}", e);
+                println!("PC: {:#05X}, opcode: {:#06X}", cpu.program_counter(), cpu.current_opcode());
+                // A movie ending on the error reproduces it
+                save_movie(&movie);
+                cpu.remove_trace_hook();
                 process::exit(-1);
             }
         }
 
         if let Ok(refresh) = cpu.consume_refresh() {
             if refresh {
-                let buff =  cpu.get_image().unwrap();
-                display_driver.draw(buff);
+                display_driver.draw(cpu.frame_buffer());
             }
         }
 
+        let mut new_title = format!("chip-8 emulator - slot {}", slot);
+        if cpu.is_waiting_for_key() {
+            new_title.push_str(" - waiting for key");
+        }
+        if new_title != title {
+            display_driver.set_title(&new_title);
+            title = new_title;
+        }
+
         if let Ok(sound) = cpu.sound() {
             if sound {
+                audio_driver.set_pattern(cpu.audio_pattern(), cpu.playback_rate());
                 audio_driver.start_beep();
             }
             else {
@@ -106,6 +395,15 @@ fn main() {
             }
         }
 
-
+        // Wall clock pacing, frames late are not caught up
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            sleep(next_frame - now);
+        }
+        else {
+            next_frame = now;
+        }
     }
-}
\ No newline at end of file
+    save_movie(&movie);
+}
@@ -0,0 +1,195 @@
+use std::io::{Read, Write};
+
+use crate::cpu::{CPU, FrameBuffer, KEY_COUNT, PROGRAM_START, read_u8, read_u16, read_u32, read_u64};
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+
+pub const MOVIE_MAGIC: [u8; 4] = *b"C8MV";
+pub const MOVIE_VERSION: u8 = 2;
+/// Frames between two screen checksums
+pub const CHECKSUM_INTERVAL: u32 = 60;
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// Keypad state of every frame since power on, with what it takes to play them
+/// again the same way: quirks, frequency, random seed and the program.
+/// Checksums of the screen taken along the way reveal replays going astray
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    quirks: Quirks,
+    frequency: u32,
+    seed: u64,
+    rom_hash: u64,
+    /// Keypad of each frame, bit i for key i
+    keys: Vec<u16>,
+    /// Screen checksum at the end of every CHECKSUM_INTERVAL frames
+    checksums: Vec<u64>,
+    /// Screen checksum at the end of the last frame, 0 without frames
+    final_checksum: u64,
+}
+
+impl Movie {
+
+    /// Starts recording `cpu`, which must be powered on without having run yet.
+    /// Random sources set by the host can't be recorded
+    pub fn record(cpu: &CPU) -> Result<Movie, Chip8Error> {
+        let seed = cpu.seed()
+            .ok_or_else(|| Chip8Error::InvalidMovie("random numbers without a seed can't be recorded".to_string()))?;
+        Ok(Movie {
+            quirks: *cpu.quirks(),
+            frequency: cpu.frequency(),
+            seed,
+            rom_hash: rom_hash(cpu),
+            keys: Vec::new(),
+            checksums: Vec::new(),
+            final_checksum: 0,
+        })
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Frames recorded
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Adds a frame once run, `keys` being the keypad it ran with
+    pub fn push_frame(&mut self, keys: &[bool; KEY_COUNT], cpu: &CPU) {
+        let bits = keys.iter().enumerate().fold(0, |bits, (key, down)| bits | (*down as u16) << key);
+        self.keys.push(bits);
+        // Frames after the last interval are checked through the final checksum
+        self.final_checksum = checksum(cpu.frame_buffer());
+        if self.keys.len() % CHECKSUM_INTERVAL as usize == 0 {
+            self.checksums.push(self.final_checksum);
+        }
+    }
+
+    /// Keypad to run `frame` with, None past the end of the movie
+    pub fn keys(&self, frame: usize) -> Option<[bool; KEY_COUNT]> {
+        let bits = *self.keys.get(frame)?;
+        let mut keys = [false; KEY_COUNT];
+        for (key, down) in keys.iter_mut().enumerate() {
+            *down = bits & (1 << key) != 0;
+        }
+        Some(keys)
+    }
+
+    /// Sets up a CPU created with the quirks and frequency of the movie, the program
+    /// loaded, to play the movie
+    pub fn start_replay(&self, cpu: &mut CPU) -> Result<(), Chip8Error> {
+        if *cpu.quirks() != self.quirks || cpu.frequency() != self.frequency {
+            return Err(Chip8Error::InvalidMovie("recorded with other quirks or frequency".to_string()))
+        }
+        let found = rom_hash(cpu);
+        if found != self.rom_hash {
+            return Err(Chip8Error::RomMismatch { expected: self.rom_hash, found })
+        }
+        cpu.set_seed(self.seed);
+        Ok(())
+    }
+
+    /// Compares the screen with the recording, once `frame` ran
+    pub fn verify_frame(&self, frame: usize, cpu: &CPU) -> Result<(), Chip8Error> {
+        let interval = CHECKSUM_INTERVAL as usize;
+        let expected = if frame + 1 == self.len() {
+            Some(&self.final_checksum)
+        }
+        else if (frame + 1) % interval == 0 {
+            self.checksums.get((frame + 1) / interval - 1)
+        }
+        else {
+            None
+        };
+        match expected {
+            Some(expected) if *expected != checksum(cpu.frame_buffer()) => Err(Chip8Error::Desync { frame }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the movie, little endian, after MOVIE_MAGIC and MOVIE_VERSION
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Chip8Error> {
+        writer.write_all(&MOVIE_MAGIC)?;
+        writer.write_all(&[MOVIE_VERSION, self.quirks.to_bits()])?;
+        writer.write_all(&self.frequency.to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&self.rom_hash.to_le_bytes())?;
+        writer.write_all(&CHECKSUM_INTERVAL.to_le_bytes())?;
+        writer.write_all(&(self.keys.len() as u32).to_le_bytes())?;
+        for keys in self.keys.iter() {
+            writer.write_all(&keys.to_le_bytes())?;
+        }
+        writer.write_all(&(self.checksums.len() as u32).to_le_bytes())?;
+        for checksum in self.checksums.iter() {
+            writer.write_all(&checksum.to_le_bytes())?;
+        }
+        writer.write_all(&self.final_checksum.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Reads a movie written by write
+    pub fn read(mut reader: impl Read) -> Result<Movie, Chip8Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MOVIE_MAGIC {
+            return Err(Chip8Error::InvalidMovie("not a CHIP-8 movie".to_string()))
+        }
+        let version = read_u8(&mut reader)?;
+        if version != MOVIE_VERSION {
+            return Err(Chip8Error::InvalidMovie(format!("version {} is not supported", version)))
+        }
+        let quirks = Quirks::from_bits(read_u8(&mut reader)?);
+        let frequency = read_u32(&mut reader)?;
+        let seed = read_u64(&mut reader)?;
+        let rom_hash = read_u64(&mut reader)?;
+        if read_u32(&mut reader)? != CHECKSUM_INTERVAL {
+            return Err(Chip8Error::InvalidMovie("unexpected checksum interval".to_string()))
+        }
+
+        let frames = read_u32(&mut reader)? as usize;
+        let mut keys = Vec::new();
+        for _ in 0..frames {
+            keys.push(read_u16(&mut reader)?);
+        }
+        let count = read_u32(&mut reader)? as usize;
+        if count != frames / CHECKSUM_INTERVAL as usize {
+            return Err(Chip8Error::InvalidMovie(format!("{} checksums for {} frames", count, frames)))
+        }
+        let mut checksums = Vec::new();
+        for _ in 0..count {
+            checksums.push(read_u64(&mut reader)?);
+        }
+        let final_checksum = read_u64(&mut reader)?;
+        Ok(Movie { quirks, frequency, seed, rom_hash, keys, checksums, final_checksum })
+    }
+}
+
+/// Hash of the memory from PROGRAM_START on, the program as loaded before it runs
+pub fn rom_hash(cpu: &CPU) -> u64 {
+    let memory = (PROGRAM_START..cpu.memory_size()).map(|addr| cpu.read_memory(addr).unwrap_or(0));
+    fnv1a(memory)
+}
+
+/// Hash of the resolution and pixels of the screen
+pub fn checksum(frame_buffer: &FrameBuffer) -> u64 {
+    let size = [frame_buffer.width() as u8, frame_buffer.height() as u8];
+    fnv1a(size.into_iter().chain(frame_buffer.rows().flatten().copied()))
+}
+
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(FNV_OFFSET, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use crate::error::Chip8Error;
+
+/// Behaviour switches for the instructions CHIP-8 variants disagree on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6 / 8xyE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// Fx55 / Fx65 leave I pointing right after the last register copied
+    pub load_store_increments_i: bool,
+    /// Bnnn jumps to nnn + Vx, x being the highest nibble of nnn, instead of nnn + V0
+    pub jump_with_vx: bool,
+    /// Dxyn clips sprites at the screen edges instead of wrapping them around
+    pub clip_sprites: bool,
+    /// 8xy1 / 8xy2 / 8xy3 reset VF to 0
+    pub vf_reset: bool,
+    /// Dxyn waits for the next 60Hz refresh before drawing
+    pub display_wait: bool,
+    /// 64 KiB of memory as XO-CHIP, instead of 4 KiB
+    pub extended_memory: bool,
+    /// With load_store_increments_i, I moves by x instead of x + 1 and stays on the
+    /// last address copied, as on CHIP-48
+    pub load_store_increments_i_by_x: bool,
+}
+
+impl Quirks {
+
+    /// Original COSMAC VIP interpreter
+    pub fn chip8() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+            extended_memory: false,
+            load_store_increments_i_by_x: false,
+        }
+    }
+
+    /// HP48 calculators interpreter, SUPER-CHIP but for Fx55 / Fx65 moving I by x
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            jump_with_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            extended_memory: false,
+            load_store_increments_i_by_x: true,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_with_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            extended_memory: false,
+            load_store_increments_i_by_x: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+            extended_memory: true,
+            load_store_increments_i_by_x: false,
+        }
+    }
+
+    /// Packs the switches in a byte, in declaration order from the lowest bit
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_with_vx,
+            self.clip_sprites,
+            self.vf_reset,
+            self.display_wait,
+            self.extended_memory,
+            self.load_store_increments_i_by_x,
+        ].iter().enumerate().fold(0, |bits, (i, on)| bits | (*on as u8) << i)
+    }
+
+    /// Inverse of to_bits
+    pub fn from_bits(bits: u8) -> Quirks {
+        let bit = |i: u8| bits & (1 << i) != 0;
+        Quirks {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            jump_with_vx: bit(2),
+            clip_sprites: bit(3),
+            vf_reset: bit(4),
+            display_wait: bit(5),
+            extended_memory: bit(6),
+            load_store_increments_i_by_x: bit(7),
+        }
+    }
+
+    /// Names accepted by from_str, in the order presets are listed
+    pub fn preset_names() -> [&'static str; 4] {
+        ["chip8", "chip48", "schip", "xochip"]
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::chip8()
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = Chip8Error;
+
+    fn from_str(name: &str) -> Result<Quirks, Chip8Error> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Quirks::chip8()),
+            "chip48" | "chip-48" => Ok(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Ok(Quirks::superchip()),
+            "xochip" | "xo-chip" => Ok(Quirks::xochip()),
+            _ => Err(Chip8Error::UnknownQuirks(name.to_string()))
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use rand::RngCore;
+
+/// Increment of the SplitMix64 state at each draw
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Where Cxnn gets its random bytes from. Any rand generator that can be cloned is one
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+    fn clone_box(&self) -> Box<dyn RandomSource>;
+}
+
+impl<R: RngCore + Clone + Send + 'static> RandomSource for R {
+    fn next_byte(&mut self) -> u8 {
+        self.next_u32() as u8
+    }
+
+    fn clone_box(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+/// Default source of the CPU. The same seed gives the same bytes on every platform,
+/// and the state after any number of draws is computed right away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    /// Generator in the state `draws` calls to next_u64 after seeding
+    pub fn resumed(seed: u64, draws: u64) -> SplitMix64 {
+        SplitMix64 { state: seed.wrapping_add(draws.wrapping_mul(GOLDEN_GAMMA)) }
+    }
+}
+
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Source owned by a CPU. Seeded sources are saved as their seed and the count of
+/// bytes drawn, sources given by the host can't be saved
+pub(crate) struct Random {
+    source: Box<dyn RandomSource>,
+    seed: Option<u64>,
+    draws: u64,
+}
+
+impl Random {
+
+    pub(crate) fn seeded(seed: u64) -> Random {
+        Random::resumed(seed, 0)
+    }
+
+    /// Seeded source that already gave `draws` bytes
+    pub(crate) fn resumed(seed: u64, draws: u64) -> Random {
+        Random {
+            source: Box::new(SplitMix64::resumed(seed, draws)),
+            seed: Some(seed),
+            draws,
+        }
+    }
+
+    pub(crate) fn custom(source: Box<dyn RandomSource>) -> Random {
+        Random {
+            source,
+            seed: None,
+            draws: 0,
+        }
+    }
+
+    pub(crate) fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub(crate) fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.draws += 1;
+        self.source.next_byte()
+    }
+}
+
+impl Clone for Random {
+    fn clone(&self) -> Random {
+        Random {
+            source: self.source.clone_box(),
+            seed: self.seed,
+            draws: self.draws,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use crate::cpu::FrameBuffer;
+use crate::error::Chip8Error;
+
+/// Gray level of each combination of the two XO-CHIP planes, as drawn by the SDL frontend
+const GRAY_LEVELS: [u8; 4] = [0, 250, 170, 85];
+/// Character of each combination of the two XO-CHIP planes in ASCII art
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+/// Largest stored deflate block
+const DEFLATE_BLOCK: usize = 0xFFFF;
+
+/// Ways to write a FrameBuffer to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One character per pixel, one line per row
+    Ascii,
+    /// Plain text portable bitmap, any lit plane being black
+    Pbm,
+    /// 8 bits grayscale PNG
+    Png,
+}
+
+impl Format {
+
+    /// Format matching a file extension, None for unknown ones
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let extension = path.rsplit_once('.')?.1;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = Chip8Error;
+
+    fn from_str(name: &str) -> Result<Format, Chip8Error> {
+        match name.to_lowercase().as_str() {
+            "ascii" | "txt" => Ok(Format::Ascii),
+            "pbm" => Ok(Format::Pbm),
+            "png" => Ok(Format::Png),
+            _ => Err(Chip8Error::UnknownFormat(name.to_string()))
+        }
+    }
+}
+
+/// File contents of the screen in `format`
+pub fn encode(frame_buffer: &FrameBuffer, format: Format) -> Vec<u8> {
+    match format {
+        Format::Ascii => ascii(frame_buffer).into_bytes(),
+        Format::Pbm => pbm(frame_buffer).into_bytes(),
+        Format::Png => png(frame_buffer),
+    }
+}
+
+/// `.` for unlit pixels, `#` for pixels lit on the first plane, `+` and `@` for the XO-CHIP ones
+pub fn ascii(frame_buffer: &FrameBuffer) -> String {
+    let mut out = String::with_capacity((frame_buffer.width() + 1) * frame_buffer.height());
+    for row in frame_buffer.rows() {
+        out.extend(row.iter().map(|pixel| ASCII_PIXELS[(*pixel & 0b11) as usize]));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn pbm(frame_buffer: &FrameBuffer) -> String {
+    let mut out = format!("P1\n{} {}\n", frame_buffer.width(), frame_buffer.height());
+    for row in frame_buffer.rows() {
+        let pixels: Vec<&str> = row.iter().map(|pixel| if *pixel != 0 { "1" } else { "0" }).collect();
+        out.push_str(&pixels.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// Uncompressed PNG, deflate stored blocks keep the encoder a few lines long
+pub fn png(frame_buffer: &FrameBuffer) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(frame_buffer.width() as u32).to_be_bytes());
+    header.extend_from_slice(&(frame_buffer.height() as u32).to_be_bytes());
+    // 8 bits grayscale, default compression and filters, no interlacing
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut raw = Vec::with_capacity((frame_buffer.width() + 1) * frame_buffer.height());
+    for row in frame_buffer.rows() {
+        raw.push(0);
+        raw.extend(row.iter().map(|pixel| GRAY_LEVELS[(*pixel & 0b11) as usize]));
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(DEFLATE_BLOCK).count();
+    for (index, block) in raw.chunks(DEFLATE_BLOCK).enumerate() {
+        zlib.push((index + 1 == blocks) as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
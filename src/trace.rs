@@ -0,0 +1,44 @@
+use std::fmt;
+
+use crate::cpu::{Addr, Instruction, VValue, GP_REGISTERS_COUNT};
+
+/// Machine state right after an instruction was executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Instructions executed since power on, this one included
+    pub cycle: u64,
+    /// Address of the instruction
+    pub pc: Addr,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub v: [VValue; GP_REGISTERS_COUNT],
+    pub i: Addr,
+    pub sp: usize,
+    pub dt: u8,
+    pub st: u8,
+}
+
+/// One line with fixed width fields, so traces can be diffed:
+/// `CYCLE PC OPCODE MNEMONIC V0=.. .. VF=.. I=.... SP=.. DT=.. ST=..`
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:010} {:04X} {:04X} {:<18}", self.cycle, self.pc, self.opcode, self.instruction.to_string())?;
+        for (index, value) in self.v.iter().enumerate() {
+            write!(f, " V{:X}={:02X}", index, value)?;
+        }
+        write!(f, " I={:04X} SP={:02} DT={:02X} ST={:02X}", self.i, self.sp, self.dt, self.st)
+    }
+}
+
+type TraceFn = dyn FnMut(&TraceEntry) + Send;
+
+/// Host callback given every TraceEntry. Cloning a CPU does not clone its hook,
+/// the clone is not traced
+#[derive(Default)]
+pub(crate) struct TraceHook(pub(crate) Option<Box<TraceFn>>);
+
+impl Clone for TraceHook {
+    fn clone(&self) -> TraceHook {
+        TraceHook(None)
+    }
+}
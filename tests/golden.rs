@@ -0,0 +1,137 @@
+//! Runs the ROMs listed in tests/roms/manifest headlessly, then reads the verdict
+//! of the ROMs checking their own results, or compares the screen of the vendored
+//! ones with the ASCII art checked in tests/golden
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::{asm, CPU, Headless, Quirks, DEFAULT_FREQUENCY};
+use chip8::headless::{KeyPress, Outcome, Until};
+use chip8::screenshot;
+
+struct Case {
+    rom: String,
+    quirks: String,
+    frames: u64,
+    presses: Vec<KeyPress>,
+}
+
+impl Case {
+
+    fn parse(line: &str) -> Case {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert!(fields.len() >= 3, "manifest line {:?} needs a ROM, quirks and frames", line);
+        Case {
+            rom: fields[0].to_string(),
+            quirks: fields[1].to_string(),
+            frames: fields[2].parse().expect("frames must be a number"),
+            presses: fields[3..].iter().map(|press| press.parse().unwrap()).collect(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        directory("roms").join(&self.rom)
+    }
+
+    /// Community ROM, checked against the screen of its documentation
+    fn is_vendored(&self) -> bool {
+        self.rom.starts_with("vendor/")
+    }
+
+    fn golden(&self) -> PathBuf {
+        let name = Path::new(&self.rom).file_stem().unwrap().to_string_lossy();
+        directory("golden").join(format!("{}-{}.txt", name, self.quirks))
+    }
+
+    /// Machine once the ROM reached its final loop, at the frequency of the
+    /// frontends, which the display_wait check of quirks.asm relies on
+    fn run(&self) -> Result<CPU, String> {
+        let quirks: Quirks = self.quirks.parse().map_err(|e| format!("{}", e))?;
+        let mut cpu = CPU::new(Some(DEFAULT_FREQUENCY), quirks);
+        cpu.loadb(self.path()).map_err(|e| format!("can't load: {}", e))?;
+        cpu.power_on();
+
+        let mut headless = Headless::new(self.frames);
+        headless.set_until(Until::Loop);
+        for press in self.presses.iter() {
+            headless.add_key_press(*press);
+        }
+        match headless.run(&mut cpu) {
+            Ok(Outcome::Reached { .. }) => Ok(cpu),
+            Ok(outcome) => Err(format!("did not reach its final loop: {:?}", outcome)),
+            Err(e) => Err(format!("{} at {:#05X}", e, cpu.program_counter())),
+        }
+    }
+
+    /// Verdict of the ROM, or its screen against the golden one when vendored
+    fn check(&self, cpu: &CPU) -> Result<(), String> {
+        if !self.is_vendored() {
+            return verdict(cpu)
+        }
+        let screen = screenshot::ascii(cpu.frame_buffer());
+        match fs::read_to_string(self.golden()) {
+            Ok(expected) if expected == screen => Ok(()),
+            Ok(expected) => Err(format!("screen differs\nexpected:\n{}got:\n{}", expected, screen)),
+            Err(e) => Err(format!("can't read {}: {}", self.golden().display(), e)),
+        }
+    }
+}
+
+/// Verdict left in V6 to V9 by tests/roms/check.asm
+fn verdict(cpu: &CPU) -> Result<(), String> {
+    match cpu.register(6) {
+        0 => Ok(()),
+        0xFF => Err(format!("made {} checks, not CHECKS", cpu.register(9))),
+        check => Err(format!("check {} got {:#04X}, expected {:#04X}", check, cpu.register(7), cpu.register(8))),
+    }
+}
+
+fn directory(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(name)
+}
+
+fn manifest() -> Vec<Case> {
+    let manifest = fs::read_to_string(directory("roms").join("manifest")).unwrap();
+    manifest.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(Case::parse).collect()
+}
+
+#[test]
+fn roms_pass() {
+    let mut failures = Vec::new();
+    for case in manifest() {
+        if let Err(e) = case.run().and_then(|cpu| case.check(&cpu)) {
+            failures.push(format!("{} ({}): {}", case.rom, case.quirks, e));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Vendored ROMs need their origin and licence on record
+#[test]
+fn vendored_roms_have_provenance() {
+    let provenance = fs::read_to_string(directory("roms").join("vendor").join("README.md")).unwrap();
+    for case in manifest().iter().filter(|case| case.is_vendored()) {
+        let file = &case.rom["vendor/".len()..];
+        assert!(
+            provenance.lines().any(|line| line.starts_with(&format!("| {} |", file))),
+            "{} is not listed in tests/roms/vendor/README.md", file
+        );
+    }
+}
+
+/// ROMs written for this suite are checked in next to their source
+#[test]
+fn roms_match_their_source() {
+    for entry in fs::read_dir(directory("roms")).unwrap() {
+        let source = entry.unwrap().path();
+        if source.extension().map_or(true, |extension| extension != "asm") || source.ends_with("check.asm") {
+            continue;
+        }
+        let rom = source.with_extension("ch8");
+        assert_eq!(
+            asm::assemble_file(&source).unwrap(),
+            fs::read(&rom).unwrap(),
+            "{} is not the assembled {}, run chip8-asm again", rom.display(), source.display()
+        );
+    }
+}
@@ -0,0 +1,78 @@
+; Shared by the test ROMs, which compare every result with the value the CHIP-8
+; documentation gives for it and end with `CALL verdict` then a jump to itself.
+; Each ROM defines CHECKS, the number of checks it makes.
+;
+; Reserved: V6 is the first failing check, counted from 1, 0 when every check
+; passed and 0xFF when the ROM did not make CHECKS checks. V7 and V8 are the
+; result and the expected value of that check, V9 the number of checks made.
+; The verdict is drawn too: "OK" and the number of checks, or "E" followed by
+; V6, V7 and V8.
+
+; VA is the result, VB the expected value
+check:
+    ADD V9, 1
+    SE VA, VB
+    JP check_failed
+    RET
+check_failed:
+    SE V6, 0
+    RET
+    LD V6, V9
+    LD V7, VA
+    LD V8, VB
+    RET
+
+verdict:
+    SE V9, CHECKS
+    JP verdict_miscounted
+    JP verdict_draw
+verdict_miscounted:
+    SNE V6, 0
+    LD V6, 0xFF
+verdict_draw:
+    CLS
+    LD VC, 0
+    LD VD, 0
+    SE V6, 0
+    JP verdict_failed
+    LD I, ok
+    DRW VC, VD, 5
+    LD VC, 8
+    LD I, ok + 5
+    DRW VC, VD, 5
+    LD VC, 20
+    LD VA, V9
+    JP print_byte
+verdict_failed:
+    LD V1, 0xE
+    LD F, V1
+    DRW VC, VD, 5
+    LD VC, 8
+    LD VA, V6
+    CALL print_byte
+    LD VA, V7
+    CALL print_byte
+    LD VA, V8
+    JP print_byte
+
+; Draws VA as two hexadecimal digits at (VC, VD), then moves VC to the next
+; cell. V1 and VF are clobbered.
+print_byte:
+    LD V1, VA
+    SHR V1, V1
+    SHR V1, V1
+    SHR V1, V1
+    SHR V1, V1
+    LD F, V1
+    DRW VC, VD, 5
+    ADD VC, 5
+    LD V1, 0x0F
+    AND V1, VA
+    LD F, V1
+    DRW VC, VD, 5
+    ADD VC, 8
+    RET
+
+ok:
+    db 0xF0, 0x90, 0x90, 0x90, 0xF0
+    db 0x90, 0xA0, 0xC0, 0xA0, 0x90
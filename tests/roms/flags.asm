@@ -0,0 +1,98 @@
+; Result and VF of the instructions setting VF, VF being 1 when 8xy4 carries,
+; when 8xy5 / 8xy7 do not borrow and when 8xy6 / 8xyE shift a 1 out. The flag is
+; written after the result, so it wins when VF is the destination.
+CHECKS = 22
+
+    ; 8xy4
+    LD V2, 0x10
+    LD V3, 0x20
+    ADD V2, V3
+    LD VB, 0x30
+    LD VE, 0
+    CALL check_flag
+    LD V2, 0xFF
+    LD V3, 0x01
+    ADD V2, V3
+    LD VB, 0x00
+    LD VE, 1
+    CALL check_flag
+
+    ; 8xy5
+    LD V2, 0x30
+    LD V3, 0x10
+    SUB V2, V3
+    LD VB, 0x20
+    LD VE, 1
+    CALL check_flag
+    LD V2, 0x30
+    LD V3, 0x30
+    SUB V2, V3
+    LD VB, 0x00
+    LD VE, 1
+    CALL check_flag
+    LD V2, 0x10
+    LD V3, 0x30
+    SUB V2, V3
+    LD VB, 0xE0
+    LD VE, 0
+    CALL check_flag
+
+    ; 8xy7
+    LD V2, 0x10
+    LD V3, 0x30
+    SUBN V2, V3
+    LD VB, 0x20
+    LD VE, 1
+    CALL check_flag
+    LD V2, 0x30
+    LD V3, 0x10
+    SUBN V2, V3
+    LD VB, 0xE0
+    LD VE, 0
+    CALL check_flag
+
+    ; 8xy6, 8xyE, shifting in place on every variant
+    LD V2, 0x05
+    SHR V2, V2
+    LD VB, 0x02
+    LD VE, 1
+    CALL check_flag
+    LD V2, 0x81
+    SHL V2, V2
+    LD VB, 0x02
+    LD VE, 1
+    CALL check_flag
+    LD V2, 0x41
+    SHL V2, V2
+    LD VB, 0x82
+    LD VE, 0
+    CALL check_flag
+
+    ; VF as the destination
+    LD VF, 0xFF
+    LD V3, 0x01
+    ADD VF, V3
+    LD VA, VF
+    LD VB, 1
+    CALL check
+    LD VF, 0x04
+    SHR VF, VF
+    LD VA, VF
+    LD VB, 0
+    CALL check
+
+    CALL verdict
+end:
+    JP end
+
+; Checks V2 against VB then VF, as left by the instruction before the call,
+; against VE
+check_flag:
+    LD V4, VF
+    LD VA, V2
+    CALL check
+    LD VA, V4
+    LD VB, VE
+    JP check
+
+include "check.asm"
@@ -0,0 +1,34 @@
+; Keys read by Fx0A, then the keys held down when ExA1 / Ex9E are checked.
+; Expects 1, 7 and C to be typed, then E held down while F is up.
+CHECKS = 5
+
+    LD VA, K
+    LD VB, 0x1
+    CALL check
+    LD VA, K
+    LD VB, 0x7
+    CALL check
+    LD VA, K
+    LD VB, 0xC
+    CALL check
+
+    ; Waits for E down, then checks F is up
+    LD V2, 0xE
+wait_e:
+    SKP V2
+    JP wait_e
+    LD VA, 0xEE
+    LD VB, 0xEE
+    CALL check
+    LD V2, 0xF
+    LD VA, 0x0F
+    SKNP V2
+    LD VA, 0xFF
+    LD VB, 0x0F
+    CALL check
+
+    CALL verdict
+end:
+    JP end
+
+include "check.asm"
@@ -0,0 +1,182 @@
+; Arithmetic, memory and flow instructions, each result checked against the
+; value Cowgod's CHIP-8 technical reference gives for it
+CHECKS = 24
+
+    ; 7xnn wraps around, without touching VF
+    LD VF, 0
+    LD VA, 0xFE
+    ADD VA, 3
+    LD VB, 0x01
+    CALL check
+    LD VA, VF
+    LD VB, 0
+    CALL check
+    ; 6xnn
+    LD VA, 0xFF
+    LD VB, 0xFF
+    CALL check
+    ; 8xy0
+    LD V2, 0x45
+    LD VA, V2
+    LD VB, 0x45
+    CALL check
+    ; 8xy1, 8xy2, 8xy3
+    LD VA, 0x20
+    LD V2, 0x03
+    OR VA, V2
+    LD VB, 0x23
+    CALL check
+    LD VA, 0xF3
+    LD V2, 0x3C
+    AND VA, V2
+    LD VB, 0x30
+    CALL check
+    LD VA, 0xF0
+    LD V2, 0xFF
+    XOR VA, V2
+    LD VB, 0x0F
+    CALL check
+    ; 8xy4, 8xy5, 8xy7
+    LD VA, 0x10
+    LD V2, 0x12
+    ADD VA, V2
+    LD VB, 0x22
+    CALL check
+    LD VA, 0x50
+    LD V2, 0x1D
+    SUB VA, V2
+    LD VB, 0x33
+    CALL check
+    LD VA, 0x04
+    LD V2, 0x40
+    SUBN VA, V2
+    LD VB, 0x3C
+    CALL check
+    ; 8xy6, 8xyE
+    LD VA, 0x05
+    SHR VA, VA
+    LD VB, 0x02
+    CALL check
+    SHL VA, VA
+    LD VB, 0x04
+    CALL check
+
+    ; 3xnn, 4xnn, 5xy0, 9xy0: VA counts the skips
+    LD VA, 0
+    LD V2, 7
+    LD V3, 7
+    SE V2, 7
+    ADD VA, 0x10
+    ADD VA, 1
+    SNE V2, 8
+    ADD VA, 0x10
+    ADD VA, 1
+    SE V2, V3
+    ADD VA, 0x10
+    ADD VA, 1
+    LD V3, 8
+    SNE V2, V3
+    ADD VA, 0x10
+    ADD VA, 1
+    SE V2, 8
+    ADD VA, 1
+    LD VB, 5
+    CALL check
+
+    ; 2nnn, 00EE
+    LD VA, 0
+    CALL add_five
+    LD VB, 5
+    CALL check
+
+    ; Bnnn, with V0 at 0, and V2 and V3 too for the interpreters adding Vx, x
+    ; being the page of jump_target in a ROM this size
+    LD V0, 0
+    LD V2, 0
+    LD V3, 0
+    JP V0, jump_target
+    LD VA, 0xEE
+jump_back:
+    LD VB, 0x0C
+    CALL check
+
+    ; Fx33 stores 234 as 2, 3, 4
+    LD V2, 234
+    LD I, digits
+    LD B, V2
+    LD V2, [I]
+    LD V3, V1
+    LD V4, V2
+    LD VA, V0
+    LD VB, 2
+    CALL check
+    LD VA, V3
+    LD VB, 3
+    CALL check
+    LD VA, V4
+    LD VB, 4
+    CALL check
+
+    ; Fx55, Fx65
+    LD V2, 0xAA
+    LD V3, 0xBB
+    LD I, buffer
+    LD [I], V3
+    LD V2, 0
+    LD V3, 0
+    LD I, buffer
+    LD V3, [I]
+    LD VA, V2
+    LD VB, 0xAA
+    CALL check
+    LD VA, V3
+    LD VB, 0xBB
+    CALL check
+
+    ; Annn, Fx1E: reads the byte at buffer + 4
+    LD I, buffer
+    LD V2, 4
+    ADD I, V2
+    LD V0, [I]
+    LD VA, V0
+    LD VB, 0x17
+    CALL check
+
+    ; Fx15, Fx07: the delay timer was not ticked yet
+    LD V2, 0xEE
+    LD DT, V2
+    LD VA, DT
+    LD VB, 0xEE
+    CALL check
+
+    ; Fx29 points I at the glyph of the digit, the glyph of 0 starting with 0xF0
+    LD V2, 0x0
+    LD F, V2
+    LD V0, [I]
+    LD VA, V0
+    LD VB, 0xF0
+    CALL check
+
+    ; Cxnn with a zero mask
+    RND VA, 0
+    LD VB, 0
+    CALL check
+
+    CALL verdict
+end:
+    JP end
+
+add_five:
+    ADD VA, 5
+    RET
+
+jump_target:
+    LD VA, 0x0C
+    JP jump_back
+
+include "check.asm"
+
+digits:
+    db 0, 0, 0
+buffer:
+    db 0, 0, 0, 0, 0x17
@@ -0,0 +1,144 @@
+; Behaviours CHIP-8 variants disagree on. The variant is picked by typing its
+; key, as in the menu of the community quirks test, and the results are checked
+; against its column of the quirks table of that test:
+;
+;                   1 CHIP-8  2 CHIP-48  3 SUPER-CHIP  4 XO-CHIP
+; vf_reset          on        off        off           off
+; shift_uses_vy     on        off        off           on
+; Fx55 / Fx65 I     x + 1     x          unchanged     x + 1
+; jump_with_vx      off       on         on            off
+; clip_sprites      on        on         on            off
+; display_wait      on        off        off           off
+CHECKS = 8
+
+    ; V5 is the offset of the column of the variant in expected
+    LD V2, K
+    LD V5, 0
+select:
+    ADD V2, -1
+    SNE V2, 0
+    JP selected
+    ADD V5, QUIRKS
+    JP select
+selected:
+
+    ; vf_reset: VF after 8xy1, 8xy2, 8xy3 is 00 when reset, 55 otherwise
+    LD VF, 0x55
+    LD V3, 0
+    OR V3, V3
+    LD VA, VF
+    LD V2, 0
+    CALL expect
+    CALL check
+    LD VF, 0x55
+    AND V3, V3
+    LD VA, VF
+    LD V2, 0
+    CALL expect
+    CALL check
+    LD VF, 0x55
+    XOR V3, V3
+    LD VA, VF
+    LD V2, 0
+    CALL expect
+    CALL check
+
+    ; shift_uses_vy: 8xy6 with Vx = 0x10, Vy = 0x40 is 20 from Vy, 08 in place
+    LD VA, 0x10
+    LD V3, 0x40
+    SHR VA, V3
+    LD V2, 1
+    CALL expect
+    CALL check
+
+    ; Fx65 right after Fx55 storing 01 02: 33 when I moved by x + 1, 02 by x,
+    ; 01 when it did not move
+    LD V0, 0x01
+    LD V1, 0x02
+    LD I, buffer
+    LD [I], V1
+    LD V0, [I]
+    LD VA, V0
+    LD V2, 2
+    CALL expect
+    CALL check
+
+    ; jump_with_vx: Bnnn with V0 = 0, V2 = 2 lands on jump_v0 or jump_vx, the
+    ; table being in 0x200 - 0x2FF
+    LD V0, 0
+    LD V2, 2
+    JP V0, jump_table
+jump_back:
+    LD V2, 3
+    CALL expect
+    CALL check
+
+    ; clip_sprites: VF after drawing on the left edge a bar drawn across the
+    ; right one, 00 when the bar was cut, 01 when it wrapped around
+    CLS
+    LD V2, 60
+    LD V3, 24
+    LD V4, 0
+    LD I, bar
+    DRW V2, V3, 1
+    DRW V4, V3, 1
+    LD VA, VF
+    LD V2, 4
+    CALL expect
+    CALL check
+
+    ; display_wait: delay timer after two draws right after a refresh, 01 when
+    ; the second draw waited for the next refresh, 02 otherwise
+    LD V2, 1
+    LD DT, V2
+sync:
+    LD V2, DT
+    SE V2, 0
+    JP sync
+    LD V2, 2
+    LD DT, V2
+    DRW V4, V3, 1
+    DRW V4, V3, 1
+    LD VA, DT
+    LD V2, 5
+    CALL expect
+    CALL check
+
+    CALL verdict
+end:
+    JP end
+
+jump_table:
+    JP jump_v0
+    JP jump_vx
+jump_v0:
+    LD VA, 0x0B
+    JP jump_back
+jump_vx:
+    LD VA, 0x2B
+    JP jump_back
+
+; Sets VB to the expected value of quirk V2 for the variant picked. V0, V2 and
+; I are clobbered.
+expect:
+    ADD V2, V5
+    LD I, expected
+    ADD I, V2
+    LD V0, [I]
+    LD VB, V0
+    RET
+
+include "check.asm"
+
+buffer:
+    db 0, 0, 0x33
+bar:
+    db 0xFF
+
+; Values of each quirk above, by variant
+QUIRKS = 6
+expected:
+    db 0x00, 0x20, 0x33, 0x0B, 0x00, 0x01
+    db 0x55, 0x08, 0x02, 0x2B, 0x00, 0x02
+    db 0x55, 0x08, 0x01, 0x2B, 0x00, 0x02
+    db 0x55, 0x20, 0x33, 0x0B, 0x01, 0x02